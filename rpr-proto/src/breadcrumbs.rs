@@ -0,0 +1,98 @@
+use std::collections::VecDeque;
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+
+pub const DEFAULT_MAX_BREADCRUMBS: usize = 100;
+// Longer messages are cut off, a few huge breadcrumbs or log records shouldn't push the report over the server's size limit
+const MAX_MESSAGE_LENGTH: usize = 1024;
+
+// Something the application did shortly before it crashed, see record_breadcrumb
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Breadcrumb {
+    // Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub category: String,
+    pub message: String,
+}
+
+impl Display for Breadcrumb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:03} [{}] {}", self.timestamp / 1000, self.timestamp % 1000, self.category, self.message)
+    }
+}
+
+// Keeps the last `capacity` entries, shared with the log records
+pub(crate) struct RingBuffer<T> {
+    entries: VecDeque<T>,
+    capacity: usize,
+}
+
+impl<T: Clone> RingBuffer<T> {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, entry: T) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.entries.len() >= self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.entries.len() > capacity {
+            self.entries.pop_front();
+        }
+    }
+
+    // Oldest first
+    pub fn to_vec(&self) -> Vec<T> {
+        self.entries.iter().cloned().collect()
+    }
+}
+
+// Process wide, so breadcrumbs can be recorded from anywhere without passing a handle around
+static BREADCRUMBS: Mutex<RingBuffer<Breadcrumb>> = Mutex::new(RingBuffer::new(DEFAULT_MAX_BREADCRUMBS));
+
+// Adds a breadcrumb to the ones generate_report includes, dropping the oldest one once there are too many
+pub fn record_breadcrumb(category: &str, message: &str) {
+    // built before taking the lock, nothing that can panic happens while holding it
+    let breadcrumb = Breadcrumb {
+        timestamp: now_millis(),
+        category: category.to_string(),
+        message: truncate(message),
+    };
+    BREADCRUMBS.lock().unwrap_or_else(|e| e.into_inner()).push(breadcrumb);
+}
+
+// 0 stops recording breadcrumbs
+pub fn set_max_breadcrumbs(capacity: usize) {
+    BREADCRUMBS.lock().unwrap_or_else(|e| e.into_inner()).set_capacity(capacity);
+}
+
+// Oldest first
+pub fn breadcrumbs() -> Vec<Breadcrumb> {
+    BREADCRUMBS.lock().unwrap_or_else(|e| e.into_inner()).to_vec()
+}
+
+pub(crate) fn truncate(message: &str) -> String {
+    let mut end = message.len().min(MAX_MESSAGE_LENGTH);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    message[..end].to_string()
+}
+
+// Milliseconds since the UNIX epoch
+pub(crate) fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_millis() as u64).unwrap_or(0)
+}
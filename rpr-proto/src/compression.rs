@@ -0,0 +1,141 @@
+use std::io::{Read, Write};
+use anyhow::{bail, Result};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use serde::{Deserialize, Serialize};
+
+// Compressing happens in the panic hook of the crashing process, so speed matters more than the last few percent
+const ZSTD_LEVEL: i32 = 3;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+// Compression of the report data, deflate uses the zlib format like HTTP's deflate encoding
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    None,
+    Deflate,
+    Zstd,
+}
+
+impl Codec {
+    // Every codec that compresses, best first
+    pub const SUPPORTED: [Codec; 2] = [Codec::Zstd, Codec::Deflate];
+
+    // The best codec both sides support, Codec::None if there is no common one
+    pub fn negotiate(offered: &[Codec]) -> Codec {
+        Codec::SUPPORTED.into_iter().find(|v| offered.contains(v)).unwrap_or(Codec::None)
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(match self {
+            Codec::None => data.to_vec(),
+            Codec::Deflate => {
+                let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()?
+            },
+            Codec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL)?,
+        })
+    }
+
+    // Fails if the decompressed data is bigger than `max_size`, so a tiny message can't expand into gigabytes
+    pub fn decompress(self, data: &[u8], max_size: usize) -> Result<Vec<u8>> {
+        let mut decompressed = vec![];
+        let read = match self {
+            Codec::None => {
+                decompressed.extend_from_slice(data);
+                data.len()
+            },
+            Codec::Deflate => ZlibDecoder::new(data).take(max_size as u64 + 1).read_to_end(&mut decompressed)?,
+            Codec::Zstd => zstd::Decoder::new(data)?.take(max_size as u64 + 1).read_to_end(&mut decompressed)?,
+        };
+
+        if read > max_size {
+            bail!("Decompressed data is bigger than the limit of {} bytes", max_size);
+        }
+        Ok(decompressed)
+    }
+
+    // Recognizes compressed data by its header, anything else is taken as uncompressed
+    pub fn detect(data: &[u8]) -> Codec {
+        if data.starts_with(&ZSTD_MAGIC) {
+            return Codec::Zstd;
+        }
+        match data {
+            // zlib: deflate with a window of at most 32K, and a header checksum
+            [cmf, flg, ..] if cmf & 0x0f == 8 && cmf >> 4 <= 7 && (*cmf as u16 * 256 + *flg as u16).is_multiple_of(31) => Codec::Deflate,
+            _ => Codec::None,
+        }
+    }
+
+    // Appended to file names of data compressed with this codec
+    pub fn extension(self) -> &'static str {
+        match self {
+            Codec::None => "",
+            Codec::Deflate => ".zz",
+            Codec::Zstd => ".zst",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Compresses well, like a backtrace does
+    fn sample() -> Vec<u8> {
+        (0..2000).flat_map(|v| format!("frame {}: app::module::function at src/module.rs:{}\n", v % 40, v % 300).into_bytes()).collect()
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = sample();
+        for codec in [Codec::None, Codec::Deflate, Codec::Zstd] {
+            let compressed = codec.compress(&data).unwrap();
+            if codec != Codec::None {
+                assert!(compressed.len() < data.len() / 4, "{:?}", codec);
+            }
+            assert_eq!(Codec::detect(&compressed), codec);
+            assert_eq!(codec.decompress(&compressed, data.len()).unwrap(), data);
+        }
+        assert_eq!(Codec::Zstd.decompress(&Codec::Zstd.compress(&[]).unwrap(), 0).unwrap(), b"");
+    }
+
+    #[test]
+    fn size_limit() {
+        let data = sample();
+        for codec in [Codec::None, Codec::Deflate, Codec::Zstd] {
+            let compressed = codec.compress(&data).unwrap();
+            assert!(codec.decompress(&compressed, data.len()).is_ok());
+            assert!(codec.decompress(&compressed, data.len() - 1).is_err(), "{:?}", codec);
+        }
+
+        // a few kilobytes that expand into 64 MiB
+        let bomb = Codec::Zstd.compress(&vec![0; 64 * 1024 * 1024]).unwrap();
+        assert!(bomb.len() < 64 * 1024);
+        assert!(Codec::Zstd.decompress(&bomb, 1024 * 1024).is_err());
+    }
+
+    #[test]
+    fn corrupt_data() {
+        let mut compressed = Codec::Deflate.compress(&sample()).unwrap();
+        compressed.truncate(compressed.len() / 2);
+        assert!(Codec::Deflate.decompress(&compressed, usize::MAX / 2).is_err());
+        assert!(Codec::Zstd.decompress(b"not zstd", 1024).is_err());
+    }
+
+    #[test]
+    fn negotiate() {
+        assert_eq!(Codec::negotiate(&[Codec::Deflate, Codec::Zstd]), Codec::Zstd);
+        assert_eq!(Codec::negotiate(&[Codec::Deflate]), Codec::Deflate);
+        assert_eq!(Codec::negotiate(&[Codec::None]), Codec::None);
+        assert_eq!(Codec::negotiate(&[]), Codec::None);
+    }
+
+    #[test]
+    fn detect_plain_json() {
+        assert_eq!(Codec::detect(b"{\"id\": 1}"), Codec::None);
+        assert_eq!(Codec::detect(b""), Codec::None);
+    }
+}
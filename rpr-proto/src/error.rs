@@ -0,0 +1,32 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::ErrorKind;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    // A single read or write took longer than the configured timeout
+    Timeout,
+    // The peer did not finish the exchange before the overall deadline
+    DeadlineExceeded,
+    // The length prefix of a message exceeded the maximum frame size
+    FrameTooLarge { size: u32, limit: u32 },
+}
+
+impl Display for ProtocolError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Timeout => write!(f, "connection timed out"),
+            ProtocolError::DeadlineExceeded => write!(f, "deadline exceeded"),
+            ProtocolError::FrameTooLarge { size, limit } => write!(f, "frame of {} bytes exceeds the limit of {} bytes", size, limit),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+// Socket timeouts show up as WouldBlock on unix and TimedOut on windows
+pub fn io_error(e: std::io::Error) -> anyhow::Error {
+    match e.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => ProtocolError::Timeout.into(),
+        _ => e.into(),
+    }
+}
@@ -9,27 +9,60 @@ use base64::{Engine, engine::general_purpose};
 use log::trace;
 
 mod report;
-pub use report::generate_report;
+mod breadcrumbs;
+mod compression;
+mod error;
+mod logger;
+mod transport;
+pub mod tls;
+pub use report::{generate_report, CrashReport, OsInfo, Location, Frame, Symbol};
+pub use breadcrumbs::{breadcrumbs, record_breadcrumb, set_max_breadcrumbs, Breadcrumb, DEFAULT_MAX_BREADCRUMBS};
+pub use compression::Codec;
+pub use error::{ProtocolError, io_error};
+pub use logger::{log_records, CaptureLogger, LogRecord, DEFAULT_MAX_LOG_RECORDS};
+pub use transport::Stream;
 
 type HmacSha512 = Hmac<Sha3_512>;
 
+// Bumped whenever the wire format changes
+pub const PROTOCOL_VERSION: u8 = 2;
+// Upper bound for a single message, checked before the buffer for it is allocated
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 64 * 1024;
+// Room for everything in a ReportChunk frame besides the data
+pub const CHUNK_OVERHEAD: u32 = 32;
+pub const MAX_ATTACHMENT_NAME_LENGTH: usize = 64;
+
 #[derive(Serialize, Deserialize, Debug)]
 pub enum ClientMessage {
     RequestConnection {
         application_id: [u8; 6],
+        client_version: String,
     },
     InitializeConnection {
         #[serde(with = "BigArray")]
         challenge_response: [u8; 64]
     },
-    // Starts the data stream
+    // Starts the upload, the report is sent in ReportChunks as a JSON encoded CrashReport compressed with `codec`,
+    // the size and hash are those of the compressed data
     SubmitReport {
         report_size: u32,
         report_hash: u32, // CRC32 hash
+        codec: Codec,
+        // ID of an interrupted upload of the same report to continue
+        resume_id: Option<u128>,
+        // Sent after the report in the same chunks, in this order and compressed with the same codec
+        attachments: Vec<AttachmentHeader>,
+    },
+    // The next part of the report, starting at the number of bytes acknowledged so far
+    ReportChunk {
+        offset: u32,
+        data: Vec<u8>,
+        checksum: u32, // CRC32 hash of the data
     },
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[allow(clippy::large_enum_variant)]
 pub enum ServerMessage {
     Challenge {
         #[serde(with = "BigArray")]
@@ -38,27 +71,77 @@ pub enum ServerMessage {
     ConnectionInitialized {
         version: u8,
         size_limit: u32,
+        // Days the server keeps the report for, None if kept indefinitely
+        retention_days: Option<u32>,
+        // Codecs the server accepts besides Codec::None, the size limit applies to the compressed report
+        codecs: Vec<Codec>,
+        // Limits for the attachments, each one's size is checked separately from the report's
+        max_attachments: u32,
+        attachment_size_limit: u32,
+    },
+    // Sent instead of ConnectionInitialized when the application's policy refuses the client, closes the connection
+    ConnectionRejected {
+        reason: String,
     },
-    // Closes the connection
+    // Reply to SubmitReport, the report is sent from `offset` on in chunks of at most `chunk_size` bytes.
+    // The offset is 0 unless an interrupted upload is resumed, otherwise the server starts a new one with a new ID
+    UploadAccepted {
+        report_id: u128,
+        offset: u32,
+        chunk_size: u32,
+    },
+    // Acknowledges every chunk but the last one, `received` is the number of bytes received so far
+    ChunkAck {
+        received: u32,
+    },
+    // Reply to the last chunk, closes the connection
     ReportReceived {
-        report_id: u128
+        report_id: u128,
+        // Reports of the same crash the server has received including this one, above 1 if the crash was already known
+        occurrences: u64,
+    }
+}
+
+// A named file sent along with the report, e.g. a log file. The size and hash are those of the compressed data.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentHeader {
+    pub name: String,
+    pub size: u32,
+    pub hash: u32, // CRC32 hash
+}
+
+// Attachment names end up as file names on the server, so they are kept to a safe subset
+pub fn check_attachment_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() || name.len() > MAX_ATTACHMENT_NAME_LENGTH {
+        return Err("must be 1 to 64 characters long");
+    }
+    if name.starts_with('.') || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+        return Err("may only contain letters, digits, '.', '_' and '-', and must not start with '.'");
     }
+    Ok(())
 }
 
 pub fn send_message<W: Write, S: Serialize>(writer: &mut W, message: S) -> Result<()> {
     let message_bin = bincode::serialize(&message)?;
 
-    writer.write_all(&(message_bin.len() as u32).to_le_bytes())?;
-    writer.write_all(&message_bin)?;
+    writer.write_all(&(message_bin.len() as u32).to_le_bytes()).map_err(io_error)?;
+    writer.write_all(&message_bin).map_err(io_error)?;
     Ok(())
 }
 
 pub fn receive_message<R: Read, S: DeserializeOwned>(reader: &mut R) -> Result<S> {
+    receive_message_limited(reader, DEFAULT_MAX_FRAME_SIZE)
+}
+
+pub fn receive_message_limited<R: Read, S: DeserializeOwned>(reader: &mut R, max_size: u32) -> Result<S> {
     let mut buf = [0; 4];
-    reader.read_exact(&mut buf)?;
-    let length = u32::from_le_bytes(buf) as usize;
-    let mut dbuf = vec![0; length];
-    reader.read_exact(&mut dbuf)?;
+    reader.read_exact(&mut buf).map_err(io_error)?;
+    let length = u32::from_le_bytes(buf);
+    if length > max_size {
+        return Err(ProtocolError::FrameTooLarge { size: length, limit: max_size }.into());
+    }
+    let mut dbuf = vec![0; length as usize];
+    reader.read_exact(&mut dbuf).map_err(io_error)?;
     Ok(bincode::deserialize(&dbuf)?)
 }
 
@@ -76,6 +159,40 @@ pub fn solve_challenge(data: [u8; 512], key: &str) -> Result<[u8; 64]> {
     Ok(awnser)
 }
 
-pub fn compute_hash(data: &Vec<u8>) -> u32 {
+pub fn compute_hash(data: &[u8]) -> u32 {
     crc32fast::hash(data)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn oversized_frame_is_refused_unread() {
+        let mut data = 5000u32.to_le_bytes().to_vec();
+        data.extend([0xaa; 5000]);
+        let mut reader = Cursor::new(data);
+
+        let error = receive_message_limited::<_, Vec<u8>>(&mut reader, 1024).unwrap_err();
+        assert_eq!(error.downcast_ref::<ProtocolError>(), Some(&ProtocolError::FrameTooLarge { size: 5000, limit: 1024 }));
+        // only the length prefix was read
+        assert_eq!(reader.position(), 4);
+    }
+
+    #[test]
+    fn frame_at_the_limit() {
+        let mut data = vec![];
+        send_message(&mut data, 7u32).unwrap();
+        let size = data.len() as u32 - 4;
+        assert_eq!(receive_message_limited::<_, u32>(&mut Cursor::new(&data), size).unwrap(), 7);
+        assert!(receive_message_limited::<_, u32>(&mut Cursor::new(&data), size - 1).is_err());
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let mut data = vec![];
+        send_message(&mut data, "a message".to_string()).unwrap();
+        data.truncate(data.len() - 1);
+        assert!(receive_message::<_, String>(&mut Cursor::new(data)).is_err());
+    }
+}
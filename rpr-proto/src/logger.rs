@@ -0,0 +1,116 @@
+use std::fmt::{self, Display, Formatter};
+use std::sync::Mutex;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::{Serialize, Deserialize};
+use crate::breadcrumbs::{now_millis, truncate, RingBuffer};
+
+pub const DEFAULT_MAX_LOG_RECORDS: usize = 200;
+
+// A log record captured by CaptureLogger
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LogRecord {
+    // Milliseconds since the UNIX epoch
+    pub timestamp: u64,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+}
+
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:03} {:<5} {}: {}", self.timestamp / 1000, self.timestamp % 1000, self.level, self.target, self.message)
+    }
+}
+
+static LOG_RECORDS: Mutex<RingBuffer<LogRecord>> = Mutex::new(RingBuffer::new(0));
+
+// Oldest first, empty unless a CaptureLogger is installed
+pub fn log_records() -> Vec<LogRecord> {
+    LOG_RECORDS.lock().unwrap_or_else(|e| e.into_inner()).to_vec()
+}
+
+// A log::Log that passes every record on to the logger it wraps, and keeps the most recent ones
+// at or above its level for the crash report, e.g. to wrap env_logger:
+//
+//     let logger = env_logger::Builder::from_default_env().build();
+//     let filter = logger.filter();
+//     CaptureLogger::new(Box::new(logger), filter).level(LevelFilter::Debug).init()?;
+pub struct CaptureLogger {
+    inner: Option<Box<dyn Log>>,
+    // What the wrapped logger wants to see, log::max_level() is set to let through what either side wants
+    inner_level: LevelFilter,
+    level: LevelFilter,
+    max_records: usize,
+}
+
+impl CaptureLogger {
+    pub fn new(inner: Box<dyn Log>, inner_level: LevelFilter) -> Self {
+        Self {
+            inner: Some(inner),
+            inner_level,
+            level: LevelFilter::Info,
+            max_records: DEFAULT_MAX_LOG_RECORDS,
+        }
+    }
+
+    // Only captures records, for applications that have no logger of their own
+    pub fn standalone() -> Self {
+        Self {
+            inner: None,
+            inner_level: LevelFilter::Off,
+            level: LevelFilter::Info,
+            max_records: DEFAULT_MAX_LOG_RECORDS,
+        }
+    }
+
+    // Records below this level are passed on but not captured
+    pub fn level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    pub fn max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    // Installs this as the global logger, fails if there already is one
+    pub fn init(self) -> Result<(), SetLoggerError> {
+        let max_level = self.level.max(self.inner_level);
+        LOG_RECORDS.lock().unwrap_or_else(|e| e.into_inner()).set_capacity(self.max_records);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl Log for CaptureLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level || self.inner.as_ref().is_some_and(|v| v.enabled(metadata))
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() <= self.level {
+            // formatted before taking the lock, nothing that can panic happens while holding it
+            let captured = LogRecord {
+                timestamp: now_millis(),
+                level: record.level().to_string(),
+                target: record.target().to_string(),
+                message: truncate(&record.args().to_string()),
+            };
+            LOG_RECORDS.lock().unwrap_or_else(|e| e.into_inner()).push(captured);
+        }
+
+        if let Some(inner) = &self.inner {
+            if inner.enabled(record.metadata()) {
+                inner.log(record);
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(inner) = &self.inner {
+            inner.flush();
+        }
+    }
+}
@@ -1,79 +1,193 @@
-use os_info;
-use backtrace;
-use std::fmt::Write;
-use std::panic::PanicInfo;
+use std::fmt::{self, Display, Formatter, Write};
+use std::panic::PanicHookInfo;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use crate::breadcrumbs::{self, Breadcrumb};
+use crate::logger::{self, LogRecord};
 
 const HEX_WIDTH: usize = std::mem::size_of::<usize>() + 2;
 const NEXT_SYMBOL_PADDING: usize = HEX_WIDTH + 6;
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-// based on handle_dump in https://github.com/rust-cli/human-panic
-pub fn generate_report(info: &PanicInfo) -> String {
-    let mut report: Vec<String> = vec![];
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CrashReport {
+    pub os: OsInfo,
+    pub message: Option<String>,
+    pub location: Option<Location>,
+    pub backtrace: Vec<Frame>,
+    pub crate_version: String,
+    // Seconds since the UNIX epoch
+    pub timestamp: u64,
+    // What the application did before the crash, oldest first
+    #[serde(default)]
+    pub breadcrumbs: Vec<Breadcrumb>,
+    // Captured by CaptureLogger, oldest first
+    #[serde(default)]
+    pub log: Vec<LogRecord>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct OsInfo {
+    pub os_type: String,
+    pub version: String,
+    pub architecture: Option<String>,
+    pub bitness: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Location {
+    pub file: String,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Frame {
+    pub ip: u64,
+    pub symbols: Vec<Symbol>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Symbol {
+    pub name: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+}
 
+impl CrashReport {
+    // The report is sent as JSON so that fields can be added without breaking older servers
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        Ok(serde_json::from_slice(data)?)
+    }
+}
+
+// based on handle_dump in https://github.com/rust-cli/human-panic
+pub fn generate_report(info: &PanicHookInfo) -> CrashReport {
     let osi = os_info::get();
-    report.push(format!("OS: {}", osi.os_type()));
-    report.push(format!("OS version: {}", osi.version()));
-    report.push(format!("Architecture: {:?}", osi.architecture()));
-    report.push(format!("Bitness: {:?}\n", osi.architecture()));
+    let os = OsInfo {
+        os_type: osi.os_type().to_string(),
+        version: osi.version().to_string(),
+        architecture: osi.architecture().map(|v| v.to_string()),
+        bitness: osi.bitness().to_string(),
+    };
 
-    match match (
+    let message = match (
         info.payload().downcast_ref::<&str>(),
         info.payload().downcast_ref::<String>(),
     ) {
         (Some(s), _) => Some(s.to_string()),
         (_, Some(s)) => Some(s.to_string()),
         (None, None) => None,
-    } {
-        Some(v) => report.push(format!("Message: {}", v)),
-        None => report.push("Message: --unknown--".to_string()),
-    }
+    };
+
+    let location = info.location().map(|v| Location {
+        file: v.file().to_string(),
+        line: v.line(),
+        column: v.column(),
+    });
 
-    report.push("\n--- BACKTRACE ---".to_string());
     let backtrace = backtrace::Backtrace::new();
-    for (idx, frame) in backtrace.frames().iter().enumerate() {
-        let mut backtrace = String::new();
-        let ip = frame.ip();
+    let frames = backtrace.frames().iter().map(|frame| Frame {
+        ip: frame.ip() as u64,
+        symbols: frame.symbols().iter().map(|symbol| Symbol {
+            name: symbol.name().map(|v| v.to_string()),
+            file: symbol.filename().map(|v| v.display().to_string()),
+            line: symbol.lineno(),
+        }).collect(),
+    }).collect();
+
+    CrashReport {
+        os,
+        message,
+        location,
+        backtrace: frames,
+        crate_version: VERSION.to_string(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or(0),
+        breadcrumbs: breadcrumbs::breadcrumbs(),
+        log: logger::log_records(),
+    }
+}
 
-        let _ = write!(backtrace, "{idx:4}: {ip:HEX_WIDTH$?}");
+impl Display for CrashReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "OS: {}", self.os.os_type)?;
+        writeln!(f, "OS version: {}", self.os.version)?;
+        writeln!(f, "Architecture: {}", self.os.architecture.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "Bitness: {}", self.os.bitness)?;
+        writeln!(f, "Reporter version: {}", self.crate_version)?;
+        writeln!(f, "Timestamp: {}\n", self.timestamp)?;
 
-        let symbols = frame.symbols();
-        if symbols.is_empty() {
-            let _ = write!(backtrace, " - <unresolved>");
-            continue;
+        writeln!(f, "Message: {}", self.message.as_deref().unwrap_or("--unknown--"))?;
+        match &self.location {
+            Some(v) => writeln!(f, "Location: {}", v)?,
+            None => writeln!(f, "Location: --unknown--")?,
         }
 
-        for (idx, symbol) in symbols.iter().enumerate() {
-            //Print symbols from this address,
-            //if there are several addresses
-            //we need to put it on next line
-            if idx != 0 {
-                let _ = write!(backtrace, "\n{:1$}", "", NEXT_SYMBOL_PADDING);
+        writeln!(f, "\n--- BACKTRACE ---")?;
+        for (idx, frame) in self.backtrace.iter().enumerate() {
+            let mut backtrace = String::new();
+            let ip = frame.ip as usize as *const u8;
+
+            let _ = write!(backtrace, "{idx:4}: {ip:HEX_WIDTH$?}");
+
+            if frame.symbols.is_empty() {
+                let _ = write!(backtrace, " - <unresolved>");
             }
 
-            if let Some(name) = symbol.name() {
-                let _ = write!(backtrace, " - {name}");
-            } else {
-                let _ = write!(backtrace, " - <unknown>");
+            for (idx, symbol) in frame.symbols.iter().enumerate() {
+                //Print symbols from this address,
+                //if there are several addresses
+                //we need to put it on next line
+                if idx != 0 {
+                    let _ = write!(backtrace, "\n{:1$}", "", NEXT_SYMBOL_PADDING);
+                }
+
+                if let Some(name) = &symbol.name {
+                    let _ = write!(backtrace, " - {name}");
+                } else {
+                    let _ = write!(backtrace, " - <unknown>");
+                }
+
+                //See if there is debug information with file name and line
+                if let (Some(file), Some(line)) = (&symbol.file, symbol.line) {
+                    let _ = write!(
+                        backtrace,
+                        "\n{:3$}at {}:{}",
+                        "",
+                        file,
+                        line,
+                        NEXT_SYMBOL_PADDING
+                    );
+                }
             }
+            writeln!(f, "{}", backtrace)?;
+        }
 
-            //See if there is debug information with file name and line
-            if let (Some(file), Some(line)) = (symbol.filename(), symbol.lineno()) {
-                let _ = write!(
-                    backtrace,
-                    "\n{:3$}at {}:{}",
-                    "",
-                    file.display(),
-                    line,
-                    NEXT_SYMBOL_PADDING
-                );
+        if !self.breadcrumbs.is_empty() {
+            writeln!(f, "\n--- BREADCRUMBS ---")?;
+            for i in &self.breadcrumbs {
+                writeln!(f, "{}", i)?;
+            }
+        }
+
+        if !self.log.is_empty() {
+            writeln!(f, "\n--- LOG ---")?;
+            for i in &self.log {
+                writeln!(f, "{}", i)?;
             }
         }
-        report.push(backtrace);
-    }
 
-    report.iter().fold(String::new(), |mut acc, b| {
-        acc.push_str(b);
-        acc.push('\n');
-        acc
-    })
-}
\ No newline at end of file
+        Ok(())
+    }
+}
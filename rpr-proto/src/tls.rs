@@ -0,0 +1,246 @@
+use std::net::TcpStream;
+use std::sync::Arc;
+use anyhow::{bail, Result};
+use rustls::{CertificateError, ClientConnection, DigitallySignedStruct, RootCertStore, ServerConnection, SignatureScheme, StreamOwned};
+use rustls::client::WebPkiServerVerifier;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls_pki_types::pem::PemObject;
+use sha2::{Digest, Sha256};
+use crate::Stream;
+
+pub use rustls::{ClientConfig, ServerConfig};
+
+// SHA-256 of the DER encoded certificate, same as `openssl x509 -noout -fingerprint -sha256`
+pub fn fingerprint(certificate: &[u8]) -> [u8; 32] {
+    Sha256::digest(certificate).into()
+}
+
+pub fn parse_fingerprint(fingerprint: &str) -> Result<[u8; 32]> {
+    let hex: String = fingerprint.chars().filter(|c| *c != ':').collect();
+    if hex.len() != 64 {
+        bail!("Certificate fingerprint must be 32 hex encoded bytes");
+    }
+
+    let mut result = [0; 32];
+    for (idx, byte) in result.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[idx * 2..idx * 2 + 2], 16)?;
+    }
+    Ok(result)
+}
+
+// Either of the CA and the pin can be left out, when both are given the server has to satisfy both
+pub fn client_config(ca_pem: Option<&[u8]>, pinned_certificate: Option<[u8; 32]>) -> Result<Arc<ClientConfig>> {
+    let provider = Arc::new(ring::default_provider());
+
+    let ca = match ca_pem {
+        Some(pem) => {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_slice_iter(pem) {
+                roots.add(cert?)?;
+            }
+            if roots.is_empty() {
+                bail!("No certificates found in the CA certificate file");
+            }
+            Some(WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone()).build()?)
+        },
+        None => None,
+    };
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let config = match (ca, pinned_certificate) {
+        (Some(ca), None) => builder.with_webpki_verifier(ca).with_no_client_auth(),
+        (ca, Some(pin)) => builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCertVerifier { ca, pin, provider }))
+            .with_no_client_auth(),
+        (None, None) => bail!("TLS requires a CA certificate, a pinned certificate or both"),
+    };
+
+    Ok(Arc::new(config))
+}
+
+pub fn server_config(certificate_chain_pem: &[u8], private_key_pem: &[u8]) -> Result<Arc<ServerConfig>> {
+    let certs = CertificateDer::pem_slice_iter(certificate_chain_pem).collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        bail!("No certificates found in the certificate file");
+    }
+    let key = PrivateKeyDer::from_pem_slice(private_key_pem)?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(Arc::new(config))
+}
+
+// Checks that the name can be used to verify the server's certificate, i.e. is a valid DNS name or IP address
+pub fn check_server_name(server_name: &str) -> Result<()> {
+    ServerName::try_from(server_name)?;
+    Ok(())
+}
+
+// The handshake itself happens on the first read or write
+pub fn client_stream(tcp: TcpStream, config: Arc<ClientConfig>, server_name: &str) -> Result<Stream> {
+    let server_name = ServerName::try_from(server_name.to_string())?;
+    let conn = ClientConnection::new(config, server_name)?;
+    Ok(Stream::ClientTls(Box::new(StreamOwned::new(conn, tcp))))
+}
+
+pub fn server_stream(tcp: TcpStream, config: Arc<ServerConfig>) -> Result<Stream> {
+    let conn = ServerConnection::new(config)?;
+    Ok(Stream::ServerTls(Box::new(StreamOwned::new(conn, tcp))))
+}
+
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    ca: Option<Arc<WebPkiServerVerifier>>,
+    pin: [u8; 32],
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if fingerprint(end_entity) != self.pin {
+            return Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+        }
+
+        match &self.ca {
+            Some(ca) => ca.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now),
+            None => Ok(ServerCertVerified::assertion()),
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+
+    struct Certificates {
+        ca_pem: String,
+        leaf_pem: String,
+        leaf_der: Vec<u8>,
+        leaf_key_pem: String,
+    }
+
+    // A CA and a certificate for localhost signed by it
+    fn certificates() -> Certificates {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+
+        let leaf_key = KeyPair::generate().unwrap();
+        let leaf = CertificateParams::new(vec!["localhost".to_string()]).unwrap().signed_by(&leaf_key, &ca, &ca_key).unwrap();
+        Certificates {
+            ca_pem: ca.pem(),
+            leaf_pem: leaf.pem(),
+            leaf_der: leaf.der().to_vec(),
+            leaf_key_pem: leaf_key.serialize_pem(),
+        }
+    }
+
+    // Runs a handshake against a server using the leaf certificate
+    fn connect(certificates: &Certificates, client: Arc<ClientConfig>) -> std::io::Result<()> {
+        let server = server_config(certificates.leaf_pem.as_bytes(), certificates.leaf_key_pem.as_bytes()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let handle = std::thread::spawn(move || {
+            let mut stream = server_stream(listener.accept().unwrap().0, server).unwrap();
+            let _ = stream.handshake();
+        });
+
+        let mut stream = client_stream(TcpStream::connect(address).unwrap(), client, "localhost").unwrap();
+        let result = stream.handshake();
+        drop(stream);
+        handle.join().unwrap();
+        result
+    }
+
+    #[test]
+    fn ca_only() {
+        let certificates = certificates();
+        let config = client_config(Some(certificates.ca_pem.as_bytes()), None).unwrap();
+        assert!(connect(&certificates, config).is_ok());
+    }
+
+    #[test]
+    fn pin_only() {
+        let certificates = certificates();
+        let config = client_config(None, Some(fingerprint(&certificates.leaf_der))).unwrap();
+        assert!(connect(&certificates, config).is_ok());
+    }
+
+    #[test]
+    fn wrong_pin() {
+        let certificates = certificates();
+        let config = client_config(None, Some([0; 32])).unwrap();
+        assert!(connect(&certificates, config).is_err());
+    }
+
+    #[test]
+    fn ca_with_wrong_pin() {
+        let certificates = certificates();
+        let config = client_config(Some(certificates.ca_pem.as_bytes()), Some([0; 32])).unwrap();
+        assert!(connect(&certificates, config).is_err());
+    }
+
+    #[test]
+    fn ca_and_pin() {
+        let certificates = certificates();
+        let config = client_config(Some(certificates.ca_pem.as_bytes()), Some(fingerprint(&certificates.leaf_der))).unwrap();
+        assert!(connect(&certificates, config).is_ok());
+    }
+
+    #[test]
+    fn pin_with_other_ca() {
+        // the pin matches, the certificate isn't signed by the CA
+        let other = certificates();
+        let certificates = certificates();
+        let config = client_config(Some(other.ca_pem.as_bytes()), Some(fingerprint(&certificates.leaf_der))).unwrap();
+        assert!(connect(&certificates, config).is_err());
+    }
+
+    #[test]
+    fn fingerprint_round_trip() {
+        let formatted = "AB:".repeat(31) + "CD";
+        let parsed = parse_fingerprint(&formatted).unwrap();
+        assert_eq!(parsed[..31], [0xab; 31]);
+        assert_eq!(parsed[31], 0xcd);
+        assert!(parse_fingerprint("AB:CD").is_err());
+    }
+}
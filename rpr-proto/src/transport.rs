@@ -0,0 +1,94 @@
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use rustls::{ClientConnection, ServerConnection, StreamOwned};
+
+// A connection between client and server, either plaintext or wrapped in TLS
+pub enum Stream {
+    Plain(TcpStream),
+    ClientTls(Box<StreamOwned<ClientConnection, TcpStream>>),
+    ServerTls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    // The underlying socket, used for timeouts and peer information
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(s) => s,
+            Stream::ClientTls(s) => &s.sock,
+            Stream::ServerTls(s) => &s.sock,
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        !matches!(self, Stream::Plain(_))
+    }
+
+    // TLS handshakes normally happen on the first read or write, this finishes it right away so
+    // certificate problems show up while connecting
+    pub fn handshake(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(_) => (),
+            Stream::ClientTls(s) => {
+                let s = &mut **s;
+                while s.conn.is_handshaking() {
+                    s.conn.complete_io(&mut s.sock)?;
+                }
+            },
+            Stream::ServerTls(s) => {
+                let s = &mut **s;
+                while s.conn.is_handshaking() {
+                    s.conn.complete_io(&mut s.sock)?;
+                }
+            },
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        // let the peer know we're closing the TLS session, it's fine if that doesn't get through
+        match self {
+            Stream::Plain(_) => (),
+            Stream::ClientTls(s) => {
+                s.conn.send_close_notify();
+                let _ = s.flush();
+            },
+            Stream::ServerTls(s) => {
+                s.conn.send_close_notify();
+                let _ = s.flush();
+            },
+        }
+        // the peer may have closed the connection already, which is what we want anyway
+        match self.tcp().shutdown(how) {
+            Err(e) if e.kind() == io::ErrorKind::NotConnected => Ok(()),
+            other => other,
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            Stream::ClientTls(s) => s.read(buf),
+            Stream::ServerTls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            Stream::ClientTls(s) => s.write(buf),
+            Stream::ServerTls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            Stream::ClientTls(s) => s.flush(),
+            Stream::ServerTls(s) => s.flush(),
+        }
+    }
+}
@@ -1,54 +1,256 @@
 use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 use serde::Deserialize;
 use toml;
 use wherr::wherr;
-use log::{error, info, trace};
+use log::{debug, error, info, trace};
 use anyhow::Result;
+use semver::{Version, VersionReq};
+use base64::{Engine, engine::general_purpose};
+
+pub type Applications = HashMap<[u8; 6], Application>;
 
 #[derive(Deserialize)]
 pub struct Application {
     pub name: String,
     pub id: [u8; 6],
     pub key: String,
+    // Overrides the server's report size limit for this application
+    #[serde(default)]
+    pub max_report_size: Option<u32>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    // Reports older than this are deleted, they are kept forever when unset
+    #[serde(default)]
+    pub retention_days: Option<u32>,
+    // Version requirements the reporter has to match one of, e.g. [">=0.1.1"], any version is accepted when unset
+    #[serde(default)]
+    pub allowed_client_versions: Option<Vec<VersionReq>>,
 }
 
-#[wherr]
-pub fn load_applications() -> Result<HashMap<[u8; 6], Application>> {
-    let path = match std::env::var("APPLICATIONS_FOLDER") {
-        Ok(v) => v,
-        Err(_) => {
-            let mut path = std::env::current_dir()?.to_string_lossy().to_string();
-            path.push_str("/applications");
-            info!("Using default application directory: {}", path);
-            path.to_string()
+fn default_enabled() -> bool {
+    true
+}
+
+impl Application {
+    pub fn size_limit(&self, server_limit: u32) -> u32 {
+        self.max_report_size.unwrap_or(server_limit)
+    }
+
+    pub fn allows_client_version(&self, version: &str) -> bool {
+        let allowed = match &self.allowed_client_versions {
+            Some(v) => v,
+            None => return true,
+        };
+
+        match Version::parse(version) {
+            Ok(version) => allowed.iter().any(|v| v.matches(&version)),
+            Err(_) => false,
         }
-    };
-    let path: PathBuf = PathBuf::from(path).canonicalize()?;
+    }
+}
+
+// Every problem found while loading the folder, reported together so they can all be fixed in one go
+#[derive(Debug)]
+pub struct LoadErrors(pub Vec<(PathBuf, String)>);
+
+impl Display for LoadErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid application definitions:")?;
+        for (path, error) in &self.0 {
+            write!(f, "\n - {}: {}", path.to_string_lossy(), error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for LoadErrors {}
+
+#[wherr]
+pub fn load_applications(path: &Path) -> Result<Applications> {
+    let path = path.canonicalize()?;
 
     trace!("Reading application definitions from '{}'", path.to_string_lossy());
 
-    let mut apps: HashMap<[u8; 6], Application> = HashMap::new();
-    for i in fs::read_dir(path).unwrap() {
+    let mut files = vec![];
+    let mut errors = vec![];
+    for i in fs::read_dir(&path)? {
         match i {
-            Ok(v) => {
-                trace!("Reading '{}'", v.path().to_string_lossy());
-                let data = fs::read_to_string(v.path())?;
-                let table: toml::Table = toml::from_str(&data)?;
-
-                for i in table {
-                    let appdef: Application = i.1.try_into()?;
-                    trace!("Loaded application definition '{}'", appdef.name);
-                    apps.insert(appdef.id, appdef);
-                }
-            },
+            Ok(v) if v.path().extension().is_some_and(|ext| ext == "toml") && v.path().is_file() => files.push(v.path()),
+            // editor swap files, backups, READMEs etc.
+            Ok(v) => trace!("Skipping '{}', not a .toml file", v.path().to_string_lossy()),
+            Err(e) => errors.push((path.clone(), format!("unable to read directory entry: {}", e))),
+        }
+    }
+    files.sort();
+
+    let mut apps: Applications = HashMap::new();
+    // where every id and name was first defined, to point at both files on duplicates
+    let mut ids: HashMap<[u8; 6], PathBuf> = HashMap::new();
+    let mut names: HashMap<String, PathBuf> = HashMap::new();
+    for file in files {
+        trace!("Reading '{}'", file.to_string_lossy());
+        let appdefs = match read_appdef_file(&file) {
+            Ok(v) => v,
             Err(e) => {
-                error!("Unable to open file: {}", e.to_string())
+                errors.push((file, e.to_string()));
+                continue;
             }
+        };
+
+        for appdef in appdefs {
+            if let Some(other) = ids.get(&appdef.id) {
+                errors.push((file.clone(), format!("application id {:?} of '{}' is already used in '{}'", appdef.id, appdef.name, other.to_string_lossy())));
+                continue;
+            }
+            if let Some(other) = names.get(&appdef.name) {
+                errors.push((file.clone(), format!("application name '{}' is already used in '{}'", appdef.name, other.to_string_lossy())));
+                continue;
+            }
+
+            trace!("Loaded application definition '{}'", appdef.name);
+            ids.insert(appdef.id, file.clone());
+            names.insert(appdef.name.clone(), file.clone());
+            apps.insert(appdef.id, appdef);
         }
     }
+
+    if !errors.is_empty() {
+        return Err(LoadErrors(errors).into());
+    }
     info!("Application definition loading finished, {} appdefs loaded", apps.len());
 
     Ok(apps)
-}
\ No newline at end of file
+}
+
+// The name is used as a folder name by the filesystem storage and as part of S3 keys
+pub fn check_application_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        anyhow::bail!("application name '{}' must not be empty, start with '.' or contain '/' or '\\'", name);
+    }
+    Ok(())
+}
+
+fn read_appdef_file(path: &Path) -> Result<Vec<Application>> {
+    let data = fs::read_to_string(path)?;
+    let table: toml::Table = toml::from_str(&data)?;
+
+    let mut appdefs = vec![];
+    for (key, value) in table {
+        let appdef: Application = value.try_into().map_err(|e| anyhow::anyhow!("[{}]: {}", key, e))?;
+        if appdef.max_report_size == Some(0) || appdef.retention_days == Some(0) {
+            anyhow::bail!("[{}]: max_report_size and retention_days must be at least 1", key);
+        }
+        if !general_purpose::STANDARD.decode(&appdef.key).is_ok_and(|v| !v.is_empty()) {
+            anyhow::bail!("[{}]: key must be non-empty base64", key);
+        }
+        check_application_name(&appdef.name).map_err(|e| anyhow::anyhow!("[{}]: {}", key, e))?;
+        appdefs.push(appdef);
+    }
+    Ok(appdefs)
+}
+
+// Holds the current application definitions, connections keep using the snapshot they started with
+pub struct ApplicationRegistry {
+    path: PathBuf,
+    current: RwLock<Arc<Applications>>,
+}
+
+impl ApplicationRegistry {
+    pub fn load(path: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            current: RwLock::new(Arc::new(load_applications(path)?)),
+        })
+    }
+
+    pub fn snapshot(&self) -> Arc<Applications> {
+        self.current.read().unwrap().clone()
+    }
+
+    // The new definitions are only swapped in if the whole folder loaded successfully
+    pub fn reload(&self) -> Result<usize> {
+        let apps = load_applications(&self.path)?;
+        let count = apps.len();
+        *self.current.write().unwrap() = Arc::new(apps);
+        Ok(count)
+    }
+
+    // Polls the folder for changes and reloads when anything was added, removed or modified
+    pub fn watch(self: Arc<Self>, interval: Duration) {
+        std::thread::spawn(move || {
+            let mut last = folder_state(&self.path);
+            loop {
+                std::thread::sleep(interval);
+                let state = folder_state(&self.path);
+                if state == last {
+                    continue;
+                }
+                last = state;
+
+                debug!("Change detected in '{}', reloading application definitions", self.path.to_string_lossy());
+                match self.reload() {
+                    Ok(count) => info!("Reloaded application definitions, {} appdefs loaded", count),
+                    Err(e) => error!("Failed to reload application definitions, keeping the previous ones: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn folder_state(path: &Path) -> Vec<(PathBuf, Option<SystemTime>, u64)> {
+    let mut state: Vec<_> = match fs::read_dir(path) {
+        Ok(v) => v.filter_map(|i| i.ok())
+            .map(|i| {
+                let metadata = i.metadata().ok();
+                (
+                    i.path(),
+                    metadata.as_ref().and_then(|v| v.modified().ok()),
+                    metadata.map(|v| v.len()).unwrap_or(0),
+                )
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    state.sort();
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(definition: &str) -> Result<Vec<Application>> {
+        let directory = tempfile::TempDir::new().unwrap();
+        let path = directory.path().join("app.toml");
+        fs::write(&path, definition).unwrap();
+        read_appdef_file(&path)
+    }
+
+    #[test]
+    fn valid_definition() {
+        let appdefs = read("[game]\nname = \"game\"\nid = [1, 2, 3, 4, 5, 6]\nkey = \"aGVsbG8=\"\n").unwrap();
+        assert_eq!(appdefs[0].name, "game");
+    }
+
+    #[test]
+    fn keys() {
+        for key in ["", "not base64!"] {
+            let definition = format!("[game]\nname = \"game\"\nid = [1, 2, 3, 4, 5, 6]\nkey = \"{}\"\n", key);
+            assert!(read(&definition).is_err(), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn names() {
+        for name in ["", ".hidden", "../x", "a/b", "a\\\\b"] {
+            let definition = format!("[game]\nname = \"{}\"\nid = [1, 2, 3, 4, 5, 6]\nkey = \"aGVsbG8=\"\n", name);
+            assert!(read(&definition).is_err(), "{:?}", name);
+        }
+        assert!(check_application_name("my-game.v2").is_ok());
+    }
+}
@@ -0,0 +1,247 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use anyhow::Result;
+use clap::{Args, Parser, Subcommand};
+use uuid::Uuid;
+use rpr_server::application::load_applications;
+use rpr_server::config::ServerConfig;
+use rpr_server::dates::{format_timestamp, parse_date};
+use rpr_server::storage::{ReportFilter, Storage};
+
+#[derive(Parser)]
+#[command(version, about = "Query and manage the reports stored by rpr-server")]
+struct Cli {
+    /// Path to the server's configuration file [default: server.toml, if it exists]
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List the application definitions and how many reports are stored for each
+    Apps,
+    /// List reports, newest first
+    List {
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Show a single report
+    Show {
+        id: Uuid,
+        /// Print the report as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write an attachment of a report, e.g. the log file sent along with it
+    Attachment {
+        id: Uuid,
+        name: String,
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Delete reports by ID
+    Delete {
+        #[arg(required = true)]
+        ids: Vec<Uuid>,
+    },
+    /// Delete every report matching the filter
+    Purge {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Only list the reports that would be deleted
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// List crash buckets, most recently seen first
+    Buckets {
+        /// Only buckets of this application
+        #[arg(short, long)]
+        app: Option<String>,
+        /// Show at most this many buckets
+        #[arg(short = 'n', long)]
+        limit: Option<usize>,
+    },
+    /// Write the reports matching the filter as a JSON array
+    Export {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+// Dates are UTC and accept 2024-03-05, 2024-03-05T17:01:22, a UNIX timestamp or a relative time like 7d
+#[derive(Args)]
+struct FilterArgs {
+    /// Only reports of this application
+    #[arg(short, long)]
+    app: Option<String>,
+    /// Only reports from this OS, e.g. Windows
+    #[arg(long)]
+    os: Option<String>,
+    /// Only reports received at or after this date
+    #[arg(long, value_parser = parse_date)]
+    since: Option<u64>,
+    /// Only reports received at or before this date
+    #[arg(long, value_parser = parse_date)]
+    until: Option<u64>,
+    /// Only reports with this crash signature
+    #[arg(long)]
+    signature: Option<String>,
+    /// Only reports in this crash bucket
+    #[arg(long)]
+    bucket: Option<i64>,
+    /// Return at most this many reports
+    #[arg(short = 'n', long)]
+    limit: Option<usize>,
+}
+
+impl FilterArgs {
+    fn filter(&self) -> ReportFilter {
+        ReportFilter {
+            app: self.app.clone(),
+            os: self.os.clone(),
+            since: self.since,
+            until: self.until,
+            signature: self.signature.clone(),
+            bucket: self.bucket,
+            limit: self.limit,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.app.is_none() && self.os.is_none() && self.since.is_none() && self.until.is_none()
+            && self.signature.is_none() && self.bucket.is_none()
+    }
+}
+
+fn main() {
+    pretty_env_logger::init();
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let (config, _) = ServerConfig::read(cli.config.as_deref())?;
+    let storage = Storage::open(&config.storage, config.index_database.as_deref())?;
+
+    match cli.command {
+        Command::Apps => {
+            let mut apps: Vec<_> = load_applications(&config.applications_folder)?.into_values().collect();
+            apps.sort_by(|a, b| a.name.cmp(&b.name));
+
+            println!("{:<24} {:<26} {:<8} {:<10} {:>8}", "NAME", "ID", "ENABLED", "RETENTION", "REPORTS");
+            for app in apps {
+                let reports = storage.query(&ReportFilter { app: Some(app.name.clone()), ..Default::default() })?.len();
+                let retention = app.retention_days.map(|v| format!("{} days", v)).unwrap_or("forever".to_string());
+                println!("{:<24} {:<26} {:<8} {:<10} {:>8}", app.name, format!("{:?}", app.id), app.enabled, retention, reports);
+            }
+        },
+        Command::List { filter } => {
+            println!("{:<36} {:<19} {:<16} {:<10} {:<8} {:>6}  MESSAGE", "ID", "RECEIVED (UTC)", "APP", "OS", "VERSION", "BUCKET");
+            for i in storage.query(&filter.filter())? {
+                println!(
+                    "{:<36} {:<19} {:<16} {:<10} {:<8} {:>6}  {}",
+                    i.id, format_timestamp(i.received_at), i.app, i.os, i.client_version,
+                    i.bucket.map(|v| v.to_string()).unwrap_or_default(), i.message.as_deref().unwrap_or("--unknown--"),
+                );
+            }
+        },
+        Command::Show { id, json } => {
+            let report = match storage.load(id)? {
+                Some(v) => v,
+                None => anyhow::bail!("No report with ID {}", id),
+            };
+
+            if json {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            } else {
+                println!("Report ID: {}", report.id);
+                println!("Application: {}", report.app);
+                println!("Received: {} UTC from {}", format_timestamp(report.received_at), report.peer);
+                println!("Client version: {}", report.client_version);
+                for i in &report.attachments {
+                    println!("Attachment: {} ({} bytes)", i.name, i.size);
+                }
+                println!();
+                print!("{}", report.report);
+            }
+        },
+        Command::Attachment { id, name, output } => {
+            let data = match storage.load_attachment(id, &name)? {
+                Some(v) => v,
+                None => anyhow::bail!("Report {} has no attachment '{}'", id, name),
+            };
+
+            match &output {
+                Some(v) => std::fs::write(v, &data)?,
+                None => io::stdout().lock().write_all(&data)?,
+            }
+        },
+        Command::Delete { ids } => {
+            for id in ids {
+                match storage.delete(id)? {
+                    true => println!("Deleted {}", id),
+                    false => println!("No report with ID {}", id),
+                }
+            }
+        },
+        Command::Purge { filter, dry_run } => {
+            if filter.is_empty() {
+                anyhow::bail!("Refusing to purge every report, give at least one filter");
+            }
+
+            let reports = storage.query(&filter.filter())?;
+            for i in &reports {
+                if dry_run {
+                    println!("Would delete {} ({}, received {} UTC)", i.id, i.app, format_timestamp(i.received_at));
+                } else {
+                    storage.delete(i.id)?;
+                }
+            }
+            if !dry_run {
+                println!("Deleted {} reports", reports.len());
+            }
+        },
+        Command::Buckets { app, limit } => {
+            println!("{:>6} {:<16} {:>7} {:<19} {:<19} {:<32} MESSAGE", "ID", "APP", "COUNT", "FIRST SEEN (UTC)", "LAST SEEN (UTC)", "LOCATION");
+            for i in storage.buckets(app.as_deref())?.into_iter().take(limit.unwrap_or(usize::MAX)) {
+                println!(
+                    "{:>6} {:<16} {:>7} {:<19} {:<19} {:<32} {}",
+                    i.id, i.app, i.count, format_timestamp(i.first_seen), format_timestamp(i.last_seen),
+                    i.location.as_deref().unwrap_or("--unknown--"), i.message.as_deref().unwrap_or("--unknown--"),
+                );
+            }
+        },
+        Command::Export { filter, output } => {
+            let mut reports = vec![];
+            for i in storage.query(&filter.filter())? {
+                match storage.load(i.id)? {
+                    Some(v) => reports.push(v),
+                    None => eprintln!("Report {} is in the index but not in storage, skipping", i.id),
+                }
+            }
+
+            let mut writer: Box<dyn Write> = match &output {
+                Some(v) => Box::new(BufWriter::new(File::create(v)?)),
+                None => Box::new(io::stdout().lock()),
+            };
+            serde_json::to_writer_pretty(&mut writer, &reports)?;
+            writeln!(writer)?;
+            writer.flush()?;
+
+            if let Some(v) = output {
+                eprintln!("Exported {} reports to '{}'", reports.len(), v.display());
+            }
+        },
+    }
+
+    Ok(())
+}
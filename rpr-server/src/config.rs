@@ -0,0 +1,372 @@
+use std::fs;
+use std::net::ToSocketAddrs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::Result;
+use clap::Parser;
+use log::LevelFilter;
+use serde::Deserialize;
+use rpr_proto::Codec;
+
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+
+#[derive(Parser)]
+#[command(version, about = "Crash report server")]
+pub struct Cli {
+    /// Path to the configuration file [default: server.toml, if it exists]
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+    /// Address to listen on, may be given multiple times
+    #[arg(short, long)]
+    pub listen: Vec<String>,
+    /// Folder containing the application definitions
+    #[arg(long)]
+    pub applications_folder: Option<PathBuf>,
+    /// Folder reports are stored in
+    #[arg(long)]
+    pub report_dir: Option<PathBuf>,
+    /// Log filter, e.g. `info` or `rpr_server=trace`
+    #[arg(long)]
+    pub log_level: Option<String>,
+    /// Maximum number of connections handled at the same time
+    #[arg(long)]
+    pub max_connections: Option<usize>,
+    /// Validate the configuration and application definitions, then exit
+    #[arg(long)]
+    pub check_config: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub listen: Vec<String>,
+    pub applications_folder: PathBuf,
+    // Seconds between checks for changed application definitions, 0 disables reloading
+    pub applications_reload_interval: u64,
+    pub log_level: String,
+    pub max_connections: usize,
+    pub limits: Limits,
+    pub rate_limit: RateLimit,
+    pub compression: CompressionConfig,
+    pub storage: StorageConfig,
+    // SQLite database with the searchable report metadata, defaults to a location depending on the storage backend
+    pub index_database: Option<PathBuf>,
+    pub tls: Option<TlsConfig>,
+    // Read only HTTP API and report listing, disabled when unset
+    pub http: Option<HttpConfig>,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    // All sizes are in bytes and all timeouts in seconds
+    pub max_report_size: u32,
+    // Size of a compressed report after decompressing it
+    pub max_decompressed_size: u32,
+    pub max_frame_size: u32,
+    // Attachments sent along with a report, 0 refuses them
+    pub max_attachments: u32,
+    pub max_attachment_size: u32,
+    pub read_timeout: u64,
+    pub write_timeout: u64,
+    pub handshake_deadline: u64,
+    // Interrupted uploads are kept this long for the client to resume them, 0 disables resuming
+    pub resume_timeout: u64,
+    // Interrupted uploads kept at most, the oldest one is dropped to make room
+    pub max_resumable_uploads: usize,
+    // Received data of all interrupted uploads together
+    pub max_resumable_bytes: usize,
+}
+
+// Token bucket per peer IP, a rate of 0 disables the limit
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimit {
+    pub connections_per_minute: u32,
+    pub burst: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompressionConfig {
+    // Offered to clients, an empty list only accepts uncompressed reports
+    pub codecs: Vec<Codec>,
+    // Keep reports compressed with the codec the client used instead of storing them decompressed
+    pub store_compressed: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "backend", rename_all = "lowercase", deny_unknown_fields)]
+pub enum StorageConfig {
+    Filesystem {
+        path: PathBuf,
+    },
+    Sqlite {
+        path: PathBuf,
+    },
+    S3 {
+        // e.g. https://s3.eu-west-1.amazonaws.com or http://localhost:9000 for MinIO
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        // AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY are used when these are unset
+        access_key: Option<String>,
+        secret_key: Option<String>,
+        #[serde(default)]
+        prefix: String,
+        // MinIO and most other S3 compatible stores need path style URLs
+        #[serde(default = "default_path_style")]
+        path_style: bool,
+    },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_path_style() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    pub certificate: PathBuf,
+    pub private_key: PathBuf,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    pub listen: String,
+    // Sent as a bearer token, or as the password of HTTP basic auth from a browser
+    pub token: String,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        // the environment variables are still honored as defaults, they predate the config file
+        let applications_folder = std::env::var("APPLICATIONS_FOLDER").unwrap_or("applications".to_string());
+
+        Self {
+            listen: vec!["0.0.0.0:9001".to_string()],
+            applications_folder: applications_folder.into(),
+            applications_reload_interval: 5,
+            log_level: "info".to_string(),
+            max_connections: 64,
+            limits: Limits::default(),
+            rate_limit: RateLimit::default(),
+            compression: CompressionConfig::default(),
+            storage: StorageConfig::default(),
+            index_database: None,
+            tls: None,
+            http: None,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_report_size: 64 * 1024,
+            max_decompressed_size: 4 * 1024 * 1024,
+            max_frame_size: rpr_proto::DEFAULT_MAX_FRAME_SIZE,
+            max_attachments: 8,
+            max_attachment_size: 1024 * 1024,
+            read_timeout: 30,
+            write_timeout: 30,
+            handshake_deadline: 10,
+            resume_timeout: 600,
+            max_resumable_uploads: 64,
+            max_resumable_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self {
+            connections_per_minute: 60,
+            burst: 10,
+        }
+    }
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            codecs: Codec::SUPPORTED.to_vec(),
+            store_compressed: false,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        let path = std::env::var("REPORT_DIR").unwrap_or("reports".to_string());
+        StorageConfig::Filesystem { path: path.into() }
+    }
+}
+
+impl Limits {
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_secs(self.read_timeout)
+    }
+
+    pub fn write_timeout(&self) -> Duration {
+        Duration::from_secs(self.write_timeout)
+    }
+
+    pub fn handshake_deadline(&self) -> Duration {
+        Duration::from_secs(self.handshake_deadline)
+    }
+
+    pub fn resume_timeout(&self) -> Duration {
+        Duration::from_secs(self.resume_timeout)
+    }
+}
+
+impl ServerConfig {
+    // Reads the config file and applies the command line overrides, returns the path of the file that was used
+    pub fn load(cli: &Cli) -> Result<(Self, Option<PathBuf>)> {
+        let (mut config, path) = Self::read(cli.config.as_deref())?;
+
+        if !cli.listen.is_empty() {
+            config.listen = cli.listen.clone();
+        }
+        if let Some(v) = &cli.applications_folder {
+            config.applications_folder = v.clone();
+        }
+        if let Some(v) = &cli.report_dir {
+            config.storage = StorageConfig::Filesystem { path: v.clone() };
+        }
+        if let Some(v) = &cli.log_level {
+            config.log_level = v.clone();
+        }
+        if let Some(v) = cli.max_connections {
+            config.max_connections = v;
+        }
+
+        Ok((config, path))
+    }
+
+    // Reads the given config file, or server.toml if it exists, without any overrides
+    pub fn read(path: Option<&Path>) -> Result<(Self, Option<PathBuf>)> {
+        let path = match path {
+            Some(v) => Some(v.to_path_buf()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|v| v.exists()),
+        };
+
+        let config = match &path {
+            Some(v) => {
+                let data = fs::read_to_string(v).map_err(|e| anyhow::anyhow!("Unable to read '{}': {}", v.display(), e))?;
+                toml::from_str(&data).map_err(|e| anyhow::anyhow!("Invalid configuration file '{}': {}", v.display(), e))?
+            },
+            None => ServerConfig::default(),
+        };
+        Ok((config, path))
+    }
+
+    // Checks everything that can be checked without starting the server, and reports all problems at once
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        if self.listen.is_empty() {
+            problems.push("at least one listen address is required".to_string());
+        }
+        for i in &self.listen {
+            if let Err(e) = i.to_socket_addrs() {
+                problems.push(format!("invalid listen address '{}': {}", i, e));
+            }
+        }
+
+        // a directive without '=' may be a module name, so only the explicit levels can be checked
+        for i in self.log_level.split(',') {
+            if let Some((_, level)) = i.split_once('=') {
+                if LevelFilter::from_str(level).is_err() {
+                    problems.push(format!("invalid log level '{}'", i));
+                }
+            }
+        }
+
+        if self.max_connections == 0 {
+            problems.push("max_connections must be at least 1".to_string());
+        }
+        if self.limits.max_report_size == 0 {
+            problems.push("limits.max_report_size must be at least 1".to_string());
+        }
+        if self.limits.max_decompressed_size < self.limits.max_report_size {
+            problems.push("limits.max_decompressed_size must be at least limits.max_report_size".to_string());
+        }
+        if self.limits.max_frame_size < 1024 {
+            problems.push("limits.max_frame_size must be at least 1024".to_string());
+        }
+        if self.limits.read_timeout == 0 || self.limits.write_timeout == 0 || self.limits.handshake_deadline == 0 {
+            problems.push("limits.read_timeout, limits.write_timeout and limits.handshake_deadline must be at least 1 second".to_string());
+        }
+        if self.rate_limit.connections_per_minute > 0 && self.rate_limit.burst == 0 {
+            problems.push("rate_limit.burst must be at least 1 when rate limiting is enabled".to_string());
+        }
+
+        if !self.applications_folder.is_dir() {
+            problems.push(format!("applications folder '{}' does not exist", self.applications_folder.display()));
+        }
+        match &self.storage {
+            StorageConfig::Filesystem { path } => {
+                if !path.is_dir() {
+                    problems.push(format!("report folder '{}' does not exist", path.display()));
+                }
+            },
+            StorageConfig::Sqlite { path } => {
+                if !parent_dir(path).is_dir() {
+                    problems.push(format!("folder for the SQLite database '{}' does not exist", path.display()));
+                }
+            },
+            StorageConfig::S3 { endpoint, bucket, .. } => {
+                if let Err(e) = url::Url::parse(endpoint) {
+                    problems.push(format!("invalid S3 endpoint '{}': {}", endpoint, e));
+                }
+                if bucket.is_empty() {
+                    problems.push("storage.bucket must not be empty".to_string());
+                }
+            },
+        }
+
+        if let Some(path) = &self.index_database {
+            if !parent_dir(path).is_dir() {
+                problems.push(format!("folder for the index database '{}' does not exist", path.display()));
+            }
+        }
+
+        if let Some(tls) = &self.tls {
+            for i in [&tls.certificate, &tls.private_key] {
+                if !i.is_file() {
+                    problems.push(format!("TLS file '{}' does not exist", i.display()));
+                }
+            }
+        }
+
+        if let Some(http) = &self.http {
+            if let Err(e) = http.listen.to_socket_addrs() {
+                problems.push(format!("invalid HTTP listen address '{}': {}", http.listen, e));
+            }
+            if self.listen.contains(&http.listen) {
+                problems.push(format!("the HTTP API can't share listen address '{}' with the report listener", http.listen));
+            }
+            if http.token.is_empty() {
+                problems.push("http.token must not be empty".to_string());
+            }
+        }
+
+        if !problems.is_empty() {
+            anyhow::bail!("Invalid configuration:\n - {}", problems.join("\n - "));
+        }
+        Ok(())
+    }
+}
+
+fn parent_dir(path: &Path) -> &Path {
+    path.parent().filter(|v| !v.as_os_str().is_empty()).unwrap_or(Path::new("."))
+}
@@ -0,0 +1,135 @@
+use anyhow::Result;
+use crate::storage;
+
+const DAY: u64 = 24 * 60 * 60;
+
+// Formats seconds since the UNIX epoch as e.g. 2024-03-05 17:01:22, always in UTC
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days((timestamp / DAY) as i64);
+    let seconds = timestamp % DAY;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+// Accepts seconds since the UNIX epoch, a UTC date like 2024-03-05 or 2024-03-05T17:01:22,
+// or a time relative to now like 30m, 12h or 7d
+pub fn parse_date(value: &str) -> Result<u64> {
+    let value = value.trim();
+    if !value.is_empty() && value.chars().all(|v| v.is_ascii_digit()) {
+        return Ok(value.parse()?);
+    }
+
+    if let Some(unit) = value.chars().last().filter(|v| ['m', 'h', 'd'].contains(v)) {
+        if let Ok(amount) = value[..value.len() - 1].parse::<u64>() {
+            let seconds = match unit {
+                'm' => 60,
+                'h' => 60 * 60,
+                _ => DAY,
+            };
+            return Ok(storage::now().saturating_sub(amount * seconds));
+        }
+    }
+
+    let (date, time) = match value.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let date: Vec<_> = date.split('-').map(|v| v.parse::<u32>()).collect::<Result<_, _>>()
+        .map_err(|_| anyhow::anyhow!("invalid date '{}'", value))?;
+    let time: Vec<_> = match time {
+        Some(v) => v.split(':').map(|v| v.parse::<u32>()).collect::<Result<_, _>>()
+            .map_err(|_| anyhow::anyhow!("invalid time in '{}'", value))?,
+        None => vec![0, 0, 0],
+    };
+
+    match (date.as_slice(), time.as_slice()) {
+        (&[year, month, day], &[hour, minute, second])
+            if (1970..10000).contains(&year) && (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day)
+                && hour < 24 && minute < 60 && second < 60 => {
+            let days = days_from_civil(year as i64, month, day);
+            Ok(days as u64 * DAY + hour as u64 * 3600 + minute as u64 * 60 + second as u64)
+        },
+        _ => anyhow::bail!("invalid date '{}', expected e.g. 2024-03-05, 2024-03-05T17:01:22, 7d or a UNIX timestamp", value),
+    }
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400)) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        for timestamp in [0, 951_782_400, 1_709_658_082, 4_102_444_799] {
+            assert_eq!(parse_date(&format_timestamp(timestamp)).unwrap(), timestamp);
+        }
+        assert_eq!(format_timestamp(1_709_658_082), "2024-03-05 17:01:22");
+        assert_eq!(parse_date("2024-03-05T17:01:22").unwrap(), 1_709_658_082);
+        assert_eq!(parse_date("2024-03-05").unwrap(), 1_709_596_800);
+        assert_eq!(parse_date(" 1709658082 ").unwrap(), 1_709_658_082);
+    }
+
+    #[test]
+    fn month_lengths() {
+        assert!(parse_date("2024-02-29").is_ok());
+        assert!(parse_date("2000-02-29").is_ok());
+        assert!(parse_date("2023-02-29").is_err());
+        assert!(parse_date("1900-02-29").is_err());
+        assert!(parse_date("2024-02-31").is_err());
+        assert!(parse_date("2024-04-31").is_err());
+        assert!(parse_date("2024-12-31").is_ok());
+        assert!(parse_date("2024-00-10").is_err());
+        assert!(parse_date("2024-01-00").is_err());
+    }
+
+    #[test]
+    fn invalid_times() {
+        assert!(parse_date("2024-03-05T24:00:00").is_err());
+        assert!(parse_date("2024-03-05T12:60:00").is_err());
+        assert!(parse_date("2024-03-05T12:00").is_err());
+        assert!(parse_date("1969-12-31").is_err());
+        assert!(parse_date("yesterday").is_err());
+        assert!(parse_date("").is_err());
+    }
+
+    #[test]
+    fn relative() {
+        let now = storage::now();
+        for (value, seconds) in [("30m", 30 * 60), ("12h", 12 * 3600), ("7d", 7 * DAY), ("0d", 0)] {
+            let parsed = parse_date(value).unwrap();
+            assert!(parsed + seconds >= now && parsed + seconds <= storage::now(), "{}", value);
+        }
+        assert!(parse_date("5w").is_err());
+        assert!(parse_date("d").is_err());
+    }
+}
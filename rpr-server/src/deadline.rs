@@ -0,0 +1,168 @@
+use std::io::{self, ErrorKind, Read};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use rustls::{ConnectionCommon, SideData};
+use serde::de::DeserializeOwned;
+use rpr_proto::{ProtocolError, Stream};
+
+// Limits the total time a peer gets to complete an exchange, on top of the per-read timeout
+pub struct Deadline {
+    expires: Instant,
+    read_timeout: Duration,
+}
+
+impl Deadline {
+    pub fn new(duration: Duration, read_timeout: Duration) -> Self {
+        Self {
+            expires: Instant::now() + duration,
+            read_timeout,
+        }
+    }
+
+    pub fn receive_message<S: DeserializeOwned>(&self, stream: &mut Stream, max_size: u32) -> Result<S> {
+        let result = rpr_proto::receive_message_limited(&mut DeadlineReader { stream: &mut *stream, deadline: self }, max_size);
+        stream.tcp().set_read_timeout(Some(self.read_timeout))?;
+
+        match result {
+            Err(e) if e.downcast_ref::<ProtocolError>() == Some(&ProtocolError::Timeout) && Instant::now() >= self.expires => {
+                Err(ProtocolError::DeadlineExceeded.into())
+            },
+            other => other,
+        }
+    }
+
+    // Every read from the socket only gets the time that is left, so a peer sending one byte at a
+    // time can't stretch a message past the deadline
+    fn read_socket(&self, mut socket: &TcpStream, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.expires.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(ErrorKind::TimedOut, ProtocolError::DeadlineExceeded));
+        }
+        socket.set_read_timeout(Some(remaining.min(self.read_timeout)))?;
+        socket.read(buf)
+    }
+}
+
+struct DeadlineReader<'a> {
+    stream: &'a mut Stream,
+    deadline: &'a Deadline,
+}
+
+impl Read for DeadlineReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.stream {
+            Stream::Plain(s) => self.deadline.read_socket(s, buf),
+            Stream::ClientTls(s) => read_tls(&mut s.conn, &s.sock, buf, self.deadline),
+            Stream::ServerTls(s) => read_tls(&mut s.conn, &s.sock, buf, self.deadline),
+        }
+    }
+}
+
+// What rustls::Stream does, except that the socket reads go through the deadline. The server's
+// TLS handshake happens in here too, on the first read.
+fn read_tls<D: SideData>(conn: &mut ConnectionCommon<D>, mut socket: &TcpStream, buf: &mut [u8], deadline: &Deadline) -> io::Result<usize> {
+    loop {
+        match conn.reader().read(buf) {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            other => return other,
+        }
+
+        while conn.wants_write() {
+            conn.write_tls(&mut socket)?;
+        }
+
+        let mut reader = SocketReader { socket, deadline };
+        if conn.read_tls(&mut reader)? == 0 {
+            // the peer closed the connection, whatever rustls makes of that is the answer
+            return match conn.reader().read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(0),
+                other => other,
+            };
+        }
+        if let Err(e) = conn.process_new_packets() {
+            // try to let the peer know what went wrong
+            let _ = conn.write_tls(&mut socket);
+            return Err(io::Error::new(ErrorKind::InvalidData, e));
+        }
+    }
+}
+
+struct SocketReader<'a> {
+    socket: &'a TcpStream,
+    deadline: &'a Deadline,
+}
+
+impl Read for SocketReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.deadline.read_socket(self.socket, buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Connects a socket to one that sends `data` a byte at a time, `interval` apart
+    fn dripping_peer(data: Vec<u8>, interval: Duration) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut peer = TcpStream::connect(address).unwrap();
+            for i in data {
+                if peer.write_all(&[i]).is_err() {
+                    return;
+                }
+                thread::sleep(interval);
+            }
+        });
+        listener.accept().unwrap().0
+    }
+
+    fn is_deadline_exceeded(result: Result<u32>) -> bool {
+        matches!(result, Err(e) if e.downcast_ref::<ProtocolError>() == Some(&ProtocolError::DeadlineExceeded))
+    }
+
+    #[test]
+    fn slow_peer_hits_the_deadline() {
+        // every byte arrives well within the read timeout, the message as a whole doesn't
+        let mut message = 100u32.to_le_bytes().to_vec();
+        message.extend([0; 100]);
+        let mut stream = Stream::Plain(dripping_peer(message, Duration::from_millis(100)));
+
+        let started = Instant::now();
+        let deadline = Deadline::new(Duration::from_millis(800), Duration::from_millis(500));
+        assert!(is_deadline_exceeded(deadline.receive_message(&mut stream, 1024)));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn message_within_deadline() {
+        let message = 4u32.to_le_bytes().iter().chain(&7u32.to_le_bytes()).copied().collect();
+        let mut stream = Stream::Plain(dripping_peer(message, Duration::from_millis(10)));
+
+        let deadline = Deadline::new(Duration::from_secs(5), Duration::from_secs(1));
+        assert_eq!(deadline.receive_message::<u32>(&mut stream, 1024).unwrap(), 7);
+    }
+
+    #[test]
+    fn slow_tls_handshake_hits_the_deadline() {
+        let key = rcgen::KeyPair::generate().unwrap();
+        let certificate = rcgen::CertificateParams::new(vec!["localhost".to_string()]).unwrap().self_signed(&key).unwrap();
+        let config = rpr_proto::tls::server_config(certificate.pem().as_bytes(), key.serialize_pem().as_bytes()).unwrap();
+
+        // the start of a ClientHello record that never gets finished in time
+        let mut hello = vec![0x16, 0x03, 0x01, 0x00, 0xc8, 0x01, 0x00, 0x00, 0xc4];
+        hello.extend([0; 0xc4]);
+        let socket = dripping_peer(hello, Duration::from_millis(100));
+        let mut stream = rpr_proto::tls::server_stream(socket, config).unwrap();
+
+        let started = Instant::now();
+        let deadline = Deadline::new(Duration::from_millis(800), Duration::from_millis(500));
+        assert!(is_deadline_exceeded(deadline.receive_message(&mut stream, 1024)));
+        assert!(started.elapsed() < Duration::from_millis(1500));
+    }
+}
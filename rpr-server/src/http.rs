@@ -0,0 +1,255 @@
+use std::fmt::Write;
+use std::io::Cursor;
+use std::sync::Arc;
+use anyhow::Result;
+use base64::{Engine, engine::general_purpose};
+use log::{error, info, trace};
+use serde::Serialize;
+use tiny_http::{Header, Method, Request, Response, Server};
+use uuid::Uuid;
+use crate::application::ApplicationRegistry;
+use crate::config::HttpConfig;
+use crate::dates::{format_timestamp, parse_date};
+use crate::storage::{IndexEntry, ReportFilter, Storage};
+
+const WORKERS: usize = 4;
+// The HTML listing is meant for browsing, an unfiltered listing of every report would be useless anyway
+const HTML_LIMIT: usize = 200;
+
+// Read only access to the stored reports, for people that can't log in to the server
+pub struct HttpApi {
+    applications: Arc<ApplicationRegistry>,
+    storage: Arc<Storage>,
+    token: String,
+}
+
+#[derive(Serialize)]
+struct AppInfo<'a> {
+    name: &'a str,
+    id: [u8; 6],
+    enabled: bool,
+    retention_days: Option<u32>,
+    max_report_size: Option<u32>,
+}
+
+type HttpResponse = Response<Cursor<Vec<u8>>>;
+
+pub fn spawn(config: &HttpConfig, applications: Arc<ApplicationRegistry>, storage: Arc<Storage>) -> Result<()> {
+    let server = Server::http(&config.listen).map_err(|e| anyhow::anyhow!("Unable to bind the HTTP API to {}: {}", config.listen, e))?;
+    let server = Arc::new(server);
+    let api = Arc::new(HttpApi {
+        applications,
+        storage,
+        token: config.token.clone(),
+    });
+    info!("Serving the HTTP API on {}", config.listen);
+
+    for _ in 0..WORKERS {
+        let server = server.clone();
+        let api = api.clone();
+        std::thread::spawn(move || loop {
+            match server.recv() {
+                Ok(request) => api.handle(request),
+                Err(e) => error!("Failed to receive HTTP request: {}", e),
+            }
+        });
+    }
+    Ok(())
+}
+
+impl HttpApi {
+    fn handle(&self, request: Request) {
+        trace!("HTTP {} {} from {:?}", request.method(), request.url(), request.remote_addr());
+        let response = if !matches!(request.method(), Method::Get | Method::Head) {
+            error_response(405, "only GET is supported")
+        } else if !self.authorized(&request) {
+            // lets browsers ask for the token, any user name is accepted
+            error_response(401, "missing or invalid token")
+                .with_header(header("WWW-Authenticate", "Basic realm=\"rpr-server\""))
+        } else {
+            let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+            match self.route(path, query) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("HTTP request for {} failed: {}", request.url(), e);
+                    error_response(500, "internal error")
+                },
+            }
+        };
+
+        if let Err(e) = request.respond(response) {
+            trace!("Failed to send HTTP response: {}", e);
+        }
+    }
+
+    // Accepts `Authorization: Bearer <token>`, or basic auth with the token as password
+    fn authorized(&self, request: &Request) -> bool {
+        let value = match request.headers().iter().find(|v| v.field.equiv("Authorization")) {
+            Some(v) => v.value.as_str(),
+            None => return false,
+        };
+
+        let token = match value.split_once(' ') {
+            Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim().to_string(),
+            Some((scheme, credentials)) if scheme.eq_ignore_ascii_case("basic") => {
+                let decoded = general_purpose::STANDARD.decode(credentials.trim()).ok().and_then(|v| String::from_utf8(v).ok());
+                match decoded.as_deref().and_then(|v| v.split_once(':')) {
+                    Some((_, password)) => password.to_string(),
+                    None => return false,
+                }
+            },
+            _ => return false,
+        };
+        constant_time_eq(token.as_bytes(), self.token.as_bytes())
+    }
+
+    fn route(&self, path: &str, query: &str) -> Result<HttpResponse> {
+        let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+        Ok(match segments.as_slice() {
+            [""] => {
+                let mut filter = match parse_filter(query) {
+                    Ok(v) => v,
+                    Err(e) => return Ok(error_response(400, &e.to_string())),
+                };
+                filter.limit = Some(filter.limit.unwrap_or(HTML_LIMIT));
+                html_response(200, &self.listing(&filter)?)
+            },
+            ["apps"] => {
+                let applications = self.applications.snapshot();
+                let mut apps: Vec<_> = applications.values().map(|v| AppInfo {
+                    name: &v.name,
+                    id: v.id,
+                    enabled: v.enabled,
+                    retention_days: v.retention_days,
+                    max_report_size: v.max_report_size,
+                }).collect();
+                apps.sort_by_key(|v| v.name);
+                json_response(&apps)?
+            },
+            ["reports"] => match parse_filter(query) {
+                Ok(filter) => json_response(&self.storage.query(&filter)?)?,
+                Err(e) => error_response(400, &e.to_string()),
+            },
+            ["reports", id] => match Uuid::parse_str(id).ok().map(|v| self.storage.load(v)).transpose()? {
+                Some(Some(report)) => json_response(&report)?,
+                _ => error_response(404, "no such report"),
+            },
+            ["reports", id, "attachments", name] => match Uuid::parse_str(id).ok().map(|v| self.storage.load_attachment(v, name)).transpose()? {
+                Some(Some(data)) => Response::from_data(data)
+                    .with_header(header("Content-Type", "application/octet-stream"))
+                    .with_header(header("Content-Disposition", &format!("attachment; filename=\"{}\"", name))),
+                _ => error_response(404, "no such attachment"),
+            },
+            ["view", id] => match Uuid::parse_str(id).ok().map(|v| self.storage.load(v)).transpose()? {
+                Some(Some(report)) => {
+                    let mut html = String::new();
+                    write!(html, "<h1>Report {}</h1><p><a href=\"/\">All reports</a> | <a href=\"/reports/{}\">JSON</a></p>", report.id, report.id)?;
+                    write!(html, "<table><tr><th>Application</th><td>{}</td></tr>", escape(&report.app))?;
+                    write!(html, "<tr><th>Received</th><td>{} UTC</td></tr>", format_timestamp(report.received_at))?;
+                    write!(html, "<tr><th>Peer</th><td>{}</td></tr>", escape(&report.peer))?;
+                    write!(html, "<tr><th>Client version</th><td>{}</td></tr>", escape(&report.client_version))?;
+                    for i in &report.attachments {
+                        write!(html, "<tr><th>Attachment</th><td><a href=\"/reports/{}/attachments/{}\">{}</a> ({} bytes)</td></tr>", report.id, escape(&i.name), escape(&i.name), i.size)?;
+                    }
+                    write!(html, "</table>")?;
+                    write!(html, "<pre>{}</pre>", escape(&report.report.to_string()))?;
+                    html_response(200, &page(&format!("Report {}", report.id), &html))
+                },
+                _ => html_response(404, &page("Not found", "<h1>No such report</h1><p><a href=\"/\">All reports</a></p>")),
+            },
+            _ => error_response(404, "not found"),
+        })
+    }
+
+    fn listing(&self, filter: &ReportFilter) -> Result<String> {
+        let field = |v: &Option<String>| escape(v.as_deref().unwrap_or(""));
+        let mut html = String::new();
+        write!(html, "<h1>Crash reports</h1><form method=\"get\" action=\"/\">")?;
+        write!(html, "<label>App <input name=\"app\" value=\"{}\"></label> ", field(&filter.app))?;
+        write!(html, "<label>OS <input name=\"os\" value=\"{}\"></label> ", field(&filter.os))?;
+        write!(html, "<label>Since <input name=\"since\" placeholder=\"2024-03-05 or 7d\" value=\"{}\"></label> ", filter.since.map(format_timestamp).unwrap_or_default())?;
+        write!(html, "<label>Signature <input name=\"signature\" value=\"{}\"></label> ", field(&filter.signature))?;
+        write!(html, "<button type=\"submit\">Filter</button></form>")?;
+
+        write!(html, "<table><tr><th>Received (UTC)</th><th>App</th><th>OS</th><th>Version</th><th>Bucket</th><th>Message</th><th>Location</th></tr>")?;
+        let reports = self.storage.query(filter)?;
+        for i in &reports {
+            write_row(&mut html, i)?;
+        }
+        write!(html, "</table><p>{} reports shown</p>", reports.len())?;
+
+        Ok(page("Crash reports", &html))
+    }
+}
+
+fn write_row(html: &mut String, entry: &IndexEntry) -> std::fmt::Result {
+    write!(
+        html,
+        "<tr><td><a href=\"/view/{}\">{}</a></td><td>{}</td><td>{} {}</td><td>{}</td><td><a href=\"/?bucket={}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+        entry.id, format_timestamp(entry.received_at), escape(&entry.app), escape(&entry.os), escape(&entry.os_version),
+        escape(&entry.client_version), entry.bucket.unwrap_or_default(), entry.bucket.unwrap_or_default(),
+        escape(entry.message.as_deref().unwrap_or("--unknown--")), escape(entry.location.as_deref().unwrap_or("--unknown--")),
+    )
+}
+
+// Takes the same filters as rpr-admin, e.g. /reports?app=game&os=windows&since=7d
+fn parse_filter(query: &str) -> Result<ReportFilter> {
+    let mut filter = ReportFilter::default();
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if value.is_empty() {
+            continue;
+        }
+        let value = value.into_owned();
+        match key.as_ref() {
+            "app" => filter.app = Some(value),
+            "os" => filter.os = Some(value),
+            "since" => filter.since = Some(parse_date(&value)?),
+            "until" => filter.until = Some(parse_date(&value)?),
+            "signature" => filter.signature = Some(value),
+            "bucket" => filter.bucket = Some(value.parse().map_err(|_| anyhow::anyhow!("invalid bucket '{}'", value))?),
+            "limit" => filter.limit = Some(value.parse().map_err(|_| anyhow::anyhow!("invalid limit '{}'", value))?),
+            _ => anyhow::bail!("unknown parameter '{}'", key),
+        }
+    }
+    Ok(filter)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn json_response<T: Serialize>(value: &T) -> Result<HttpResponse> {
+    Ok(Response::from_data(serde_json::to_vec(value)?).with_header(header("Content-Type", "application/json")))
+}
+
+fn html_response(status: u16, html: &str) -> HttpResponse {
+    Response::from_data(html.as_bytes().to_vec())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "text/html; charset=utf-8"))
+}
+
+fn error_response(status: u16, message: &str) -> HttpResponse {
+    let body = serde_json::json!({ "error": message }).to_string();
+    Response::from_data(body.into_bytes())
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn page(title: &str, body: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title><style>\
+        body {{ font-family: sans-serif; margin: 2em; }} table {{ border-collapse: collapse; }} \
+        th, td {{ text-align: left; padding: 0.2em 0.6em; border-bottom: 1px solid #ddd; }} \
+        pre {{ background: #f6f6f6; padding: 1em; overflow-x: auto; }}\
+        </style></head><body>{}</body></html>",
+        escape(title), body,
+    )
+}
+
+fn escape(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;").replace('\'', "&#39;")
+}
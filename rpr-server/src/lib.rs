@@ -0,0 +1,11 @@
+pub mod application;
+pub mod config;
+pub mod dates;
+pub mod deadline;
+pub mod http;
+pub mod limiter;
+pub mod ratelimit;
+pub mod retention;
+pub mod signature;
+pub mod storage;
+pub mod upload;
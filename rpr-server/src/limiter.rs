@@ -0,0 +1,53 @@
+use std::sync::{Arc, Condvar, Mutex};
+
+// Bounds the number of connections that are handled at the same time
+#[derive(Clone)]
+pub struct ConnectionLimiter {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+    limit: usize,
+}
+
+// Frees up the slot when dropped
+pub struct ConnectionPermit {
+    inner: Arc<(Mutex<usize>, Condvar)>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new((Mutex::new(0), Condvar::new())),
+            limit,
+        }
+    }
+
+    // Blocks until a slot is available
+    pub fn acquire(&self) -> ConnectionPermit {
+        let (lock, cvar) = &*self.inner;
+        let mut in_flight = lock.lock().unwrap();
+        while *in_flight >= self.limit {
+            in_flight = cvar.wait(in_flight).unwrap();
+        }
+        *in_flight += 1;
+
+        ConnectionPermit {
+            inner: self.inner.clone(),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        *self.inner.0.lock().unwrap()
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.inner;
+        let mut in_flight = lock.lock().unwrap();
+        *in_flight -= 1;
+        cvar.notify_one();
+    }
+}
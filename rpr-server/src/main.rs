@@ -1,54 +1,183 @@
-use std::collections::HashMap;
-use std::io::{Read, Write};
-use crate::application::{Application, load_applications};
-use std::net::{Shutdown, TcpListener, TcpStream};
+use rpr_server::application::{ApplicationRegistry, load_applications};
+use std::net::{Shutdown, TcpListener};
 use anyhow::Result;
-use log::{error, info, trace};
+use clap::Parser;
+use log::{error, info, trace, warn};
 use rand::RngCore;
 use wherr::wherr;
 use uuid::Uuid;
-use rpr_proto::{ClientMessage, ServerMessage};
-use std::fs::File;
+use rpr_proto::{AttachmentHeader, ClientMessage, Codec, CrashReport, ServerMessage};
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+use rpr_server::config::{Cli, CompressionConfig, Limits, ServerConfig};
+use rpr_server::deadline::Deadline;
+use rpr_server::http;
+use rpr_server::limiter::ConnectionLimiter;
+use rpr_server::ratelimit::RateLimiter;
+use rpr_server::retention;
+use rpr_server::storage::{self, Attachment, AttachmentInfo, Storage, StoredReport};
+use rpr_server::upload::{Upload, Uploads};
+use rpr_proto::{ProtocolError, Stream};
 
-pub mod application;
+// Handshake messages from unauthenticated peers are tiny, no reason to accept more
+const MAX_HANDSHAKE_FRAME_SIZE: u32 = 1024;
 
-const VERSION: u8 = 1;
+// Shared by all connection handlers
+struct Context {
+    applications: Arc<ApplicationRegistry>,
+    storage: Arc<Storage>,
+    limits: Limits,
+    compression: CompressionConfig,
+    uploads: Uploads,
+}
 
 #[wherr]
 fn main() -> Result<()> {
-    pretty_env_logger::init();
-    let applications = load_applications()?;
-    let report_path = match std::env::var("REPORT_DIR") {
-        Ok(v) => v.to_string(),
-        Err(_) => {
-            let mut dir = std::env::current_dir()?;
-            dir.push("reports");
-            dir = dir.canonicalize()?;
-            info!("Using default report folder '{}'", dir.to_string_lossy());
-            dir.to_string_lossy().to_string()
-        }
+    let cli = Cli::parse();
+    let (config, config_path) = ServerConfig::load(&cli)?;
+
+    // RUST_LOG still takes precedence, as it's the usual way to debug a single run
+    let mut logger = pretty_env_logger::formatted_builder();
+    logger.parse_filters(&config.log_level);
+    if let Ok(v) = std::env::var("RUST_LOG") {
+        logger.parse_filters(&v);
+    }
+    logger.init();
+
+    match config_path {
+        Some(v) => info!("Loaded configuration from '{}'", v.display()),
+        None => info!("No configuration file found, using defaults"),
+    }
+    config.validate()?;
+
+    if cli.check_config {
+        let applications = load_applications(&config.applications_folder)?;
+        println!("Configuration is valid, {} application definitions", applications.len());
+        return Ok(());
+    }
+
+    let applications = Arc::new(ApplicationRegistry::load(&config.applications_folder)?);
+    if config.applications_reload_interval > 0 {
+        applications.clone().watch(Duration::from_secs(config.applications_reload_interval));
+    }
+    let storage = Arc::new(Storage::open(&config.storage, config.index_database.as_deref())?);
+
+    let tls = match &config.tls {
+        Some(tls) => {
+            info!("TLS enabled, using certificate '{}'", tls.certificate.display());
+            Some(rpr_proto::tls::server_config(&fs::read(&tls.certificate)?, &fs::read(&tls.private_key)?)?)
+        },
+        None => None,
     };
 
-    info!("Binding TCP listener to 0.0.0.0:9001");
-    let listener = TcpListener::bind("0.0.0.0:9001")?;
-    for i in listener.incoming() {
-        match handle_connection(i?, &applications, &report_path) {
-            Ok(_) => (),
-            Err(e) => log::warn!("Connection handling failed with error: {}", e),
+    let limiter = ConnectionLimiter::new(config.max_connections);
+    info!("Handling at most {} connections concurrently", config.max_connections);
+    let rate_limiter = Arc::new(RateLimiter::new(config.rate_limit.connections_per_minute, config.rate_limit.burst));
+
+    let mut listeners = vec![];
+    for address in &config.listen {
+        info!("Binding TCP listener to {}", address);
+        listeners.push(TcpListener::bind(address)?);
+    }
+
+    if let Some(http) = &config.http {
+        http::spawn(http, applications.clone(), storage.clone())?;
+    }
+
+    let context = Arc::new(Context {
+        applications,
+        storage,
+        uploads: Uploads::new(config.limits.resume_timeout(), config.limits.max_resumable_uploads, config.limits.max_resumable_bytes),
+        limits: config.limits,
+        compression: config.compression,
+    });
+
+    let purge_context = context.clone();
+    retention::spawn_purge_task(move || {
+        for (app, days) in retention::retention_periods(&purge_context.applications.snapshot()) {
+            retention::purge_reports(&purge_context.storage, &app, days);
         }
+    });
+    let handles: Vec<_> = listeners.into_iter().map(|listener| {
+        let context = context.clone();
+        let limiter = limiter.clone();
+        let rate_limiter = rate_limiter.clone();
+        let tls = tls.clone();
+        std::thread::spawn(move || accept_connections(listener, context, limiter, rate_limiter, tls))
+    }).collect();
+
+    for i in handles {
+        let _ = i.join();
     }
 
     Ok(())
 }
 
+fn accept_connections(listener: TcpListener, context: Arc<Context>, limiter: ConnectionLimiter, rate_limiter: Arc<RateLimiter>, tls: Option<Arc<rpr_proto::tls::ServerConfig>>) {
+    for i in listener.incoming() {
+        let stream = match i {
+            Ok(v) => v,
+            Err(e) => {
+                error!("Failed to accept connection: {}", e);
+                continue;
+            }
+        };
+
+        match stream.peer_addr() {
+            Ok(addr) if !rate_limiter.check(addr.ip()) => {
+                log::warn!("Rate limit exceeded by {}, dropping connection", addr.ip());
+                continue;
+            },
+            _ => (),
+        }
+
+        if limiter.in_flight() >= limiter.limit() {
+            log::warn!("Connection limit of {} reached, waiting for a free slot", limiter.limit());
+        }
+        let permit = limiter.acquire();
+        let context = context.clone();
+        let tls = tls.clone();
+        std::thread::spawn(move || {
+            let stream = match tls {
+                Some(config) => rpr_proto::tls::server_stream(stream, config),
+                None => Ok(Stream::Plain(stream)),
+            };
+            match stream.and_then(|v| handle_connection(v, &context)) {
+                Ok(_) => (),
+                Err(e) => match protocol_error(&e) {
+                    Some(ProtocolError::DeadlineExceeded) => log::warn!("Peer did not complete the handshake in time, dropped connection"),
+                    Some(ProtocolError::Timeout) => log::warn!("Connection timed out"),
+                    Some(e @ ProtocolError::FrameTooLarge { .. }) => log::warn!("Rejected oversized message: {}", e),
+                    _ => log::warn!("Connection handling failed with error: {}", e),
+                },
+            }
+            drop(permit);
+        });
+    }
+}
+
+// Errors that went through a #[wherr] function are wrapped, so look inside as well
+fn protocol_error(e: &anyhow::Error) -> Option<&ProtocolError> {
+    match e.downcast_ref::<wherr::Wherr>() {
+        Some(v) => v.inner.downcast_ref(),
+        None => e.downcast_ref(),
+    }
+}
+
 #[wherr]
-fn handle_connection(mut stream: TcpStream, appdefs: &HashMap<[u8; 6], Application>, report_path: &str) -> Result<()> {
-    let peer_addr = stream.peer_addr()?;
-    trace!("Received connection from addr {}", peer_addr);
+fn handle_connection(mut stream: Stream, context: &Context) -> Result<()> {
+    let peer_addr = stream.tcp().peer_addr()?;
+    trace!("Received connection from addr {} [TLS: {}]", peer_addr, stream.is_tls());
+    let limits = &context.limits;
+    stream.tcp().set_read_timeout(Some(limits.read_timeout()))?;
+    stream.tcp().set_write_timeout(Some(limits.write_timeout()))?;
+    let deadline = Deadline::new(limits.handshake_deadline(), limits.read_timeout());
+    let applications = context.applications.snapshot();
 
-    let app = match rpr_proto::receive_message(&mut stream)? {
-        ClientMessage::RequestConnection { application_id } => {
-            let app = match appdefs.get(&application_id) {
+    let (app, client_version) = match deadline.receive_message(&mut stream, MAX_HANDSHAKE_FRAME_SIZE)? {
+        ClientMessage::RequestConnection { application_id, client_version } => {
+            let app = match applications.get(&application_id) {
                 Some(v) => v,
                 None => {
                     error!("Invalid application id from {}, ID {:?}, terminating connection", peer_addr, application_id);
@@ -56,8 +185,8 @@ fn handle_connection(mut stream: TcpStream, appdefs: &HashMap<[u8; 6], Applicati
                     return Ok(());
                 }
             };
-            trace!("Received connection request from {}, application '{}' appID {:?}", peer_addr, app.name, application_id);
-            app
+            trace!("Received connection request from {}, application '{}' appID {:?}, client version {}", peer_addr, app.name, application_id, client_version);
+            (app, client_version)
         },
         _ => {
             error!("Unexpected message from {}, terminating connection", peer_addr);
@@ -75,7 +204,7 @@ fn handle_connection(mut stream: TcpStream, appdefs: &HashMap<[u8; 6], Applicati
     trace!("Sent challenge to {}", peer_addr);
 
     let solution = rpr_proto::solve_challenge(challenge_data, &app.key)?;
-    match rpr_proto::receive_message(&mut stream)? {
+    match deadline.receive_message(&mut stream, MAX_HANDSHAKE_FRAME_SIZE)? {
         ClientMessage::InitializeConnection {
             challenge_response
         } => {
@@ -94,33 +223,119 @@ fn handle_connection(mut stream: TcpStream, appdefs: &HashMap<[u8; 6], Applicati
         }
     }
 
+    // the policy is only checked after authentication, so unauthenticated peers can't probe it
+    let rejection = if !app.enabled {
+        Some(format!("application '{}' is not accepting reports", app.name))
+    } else if !app.allows_client_version(&client_version) {
+        Some(format!("client version {} is not allowed", client_version))
+    } else {
+        None
+    };
+    if let Some(reason) = rejection {
+        error!("Rejected connection from {}: {}", peer_addr, reason);
+        rpr_proto::send_message(&mut stream, ServerMessage::ConnectionRejected { reason })?;
+        stream.shutdown(Shutdown::Both)?;
+        return Ok(());
+    }
+
+    let size_limit = app.size_limit(limits.max_report_size);
     rpr_proto::send_message(&mut stream, ServerMessage::ConnectionInitialized {
-        size_limit: 1024 * 64, // max 64KiB
-        version: VERSION,
+        size_limit,
+        version: rpr_proto::PROTOCOL_VERSION,
+        retention_days: app.retention_days,
+        codecs: context.compression.codecs.clone(),
+        max_attachments: limits.max_attachments,
+        attachment_size_limit: limits.max_attachment_size,
     })?;
 
-    let report = match rpr_proto::receive_message(&mut stream)? {
+    let (uuid, report, attachments, codec) = match rpr_proto::receive_message_limited(&mut stream, limits.max_frame_size)? {
         ClientMessage::SubmitReport {
             report_size,
-            report_hash
+            report_hash,
+            codec,
+            resume_id,
+            attachments,
         } => {
-            trace!("Receiving {}KiB report from {}, CRC32 {}", report_size / 1024, peer_addr, report_hash);
-            if report_size > 1024 * 64 {
+            trace!("Receiving {}KiB report with {} attachments from {}, CRC32 {}, codec {:?}", report_size / 1024, attachments.len(), peer_addr, report_hash, codec);
+            if report_size > size_limit {
                 // too big
                 error!("Report from {} too big, terminating connection", peer_addr);
                 stream.shutdown(Shutdown::Both)?;
                 return Ok(());
             }
-            
-            let mut buf = vec![0; report_size as usize];
-            stream.read_exact(&mut buf)?;
-            if !(rpr_proto::compute_hash(&buf) == report_hash) {
+            if codec != Codec::None && !context.compression.codecs.contains(&codec) {
+                error!("Report from {} uses codec {:?} which wasn't offered, terminating connection", peer_addr, codec);
+                stream.shutdown(Shutdown::Both)?;
+                return Ok(());
+            }
+            if let Err(e) = check_attachments(&attachments, limits) {
+                error!("Refused attachments from {}: {}, terminating connection", peer_addr, e);
+                stream.shutdown(Shutdown::Both)?;
+                return Ok(());
+            }
+
+            let announced = Upload::new(&app.name, report_size, report_hash, codec, attachments);
+            let resumed = resume_id.map(Uuid::from_u128)
+                .and_then(|id| context.uploads.resume(id, &announced).map(|v| (id, v)));
+            let (uuid, mut upload) = match resumed {
+                Some((id, upload)) => {
+                    info!("Resuming upload {} from {} at {} of {} bytes", id, peer_addr, upload.data.len(), upload.size());
+                    (id, upload)
+                },
+                None => (new_report_id(), announced),
+            };
+            rpr_proto::send_message(&mut stream, ServerMessage::UploadAccepted {
+                report_id: uuid.as_u128(),
+                offset: upload.data.len() as u32,
+                chunk_size: limits.max_frame_size - rpr_proto::CHUNK_OVERHEAD,
+            })?;
+
+            if let Err(e) = upload.receive_chunks(&mut stream, limits.max_frame_size) {
+                warn!("Upload {} from {} interrupted at {} of {} bytes", uuid, peer_addr, upload.data.len(), upload.size());
+                context.uploads.keep(uuid, upload);
+                return Err(e);
+            }
+
+            let (buf, received_attachments) = upload.parts();
+            if rpr_proto::compute_hash(buf) != report_hash {
                 error!("CRC32 does not match for report from {}, terminating connection", peer_addr);
                 stream.shutdown(Shutdown::Both)?;
                 return Ok(());
             }
+            let buf = match codec.decompress(buf, limits.max_decompressed_size.max(size_limit) as usize) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Unable to decompress report from {}: {}, terminating connection", peer_addr, e);
+                    stream.shutdown(Shutdown::Both)?;
+                    return Ok(());
+                }
+            };
+            let report = match CrashReport::from_bytes(&buf) {
+                Ok(v) => v,
+                Err(e) => {
+                    error!("Malformed report from {}: {}, terminating connection", peer_addr, e);
+                    stream.shutdown(Shutdown::Both)?;
+                    return Ok(());
+                }
+            };
+
+            let mut attachments = vec![];
+            for (header, data) in received_attachments {
+                let data = match rpr_proto::compute_hash(data) == header.hash {
+                    true => codec.decompress(data, limits.max_attachment_size as usize),
+                    false => Err(anyhow::anyhow!("CRC32 does not match")),
+                };
+                match data {
+                    Ok(data) => attachments.push(Attachment { name: header.name.clone(), data }),
+                    Err(e) => {
+                        error!("Invalid attachment '{}' from {}: {}, terminating connection", header.name, peer_addr, e);
+                        stream.shutdown(Shutdown::Both)?;
+                        return Ok(());
+                    }
+                }
+            }
             trace!("Report received successfully");
-            buf
+            (uuid, report, attachments, codec)
         },
         _ => {
             error!("Unexpected message from {}, terminating connection", peer_addr);
@@ -129,16 +344,49 @@ fn handle_connection(mut stream: TcpStream, appdefs: &HashMap<[u8; 6], Applicati
         }
     };
 
-    let uuid = Uuid::from_u64_pair(rand::thread_rng().next_u64(), rand::thread_rng().next_u64());
-    trace!("Generated report ID {} for report from {}", uuid, peer_addr);
+    // store before acknowledging, so the client never thinks a report was saved when it wasn't
+    let bucket = context.storage.store(&StoredReport {
+        id: uuid,
+        app: app.name.clone(),
+        received_at: storage::now(),
+        peer: peer_addr.to_string(),
+        client_version,
+        report,
+        attachments: attachments.iter().map(|v| AttachmentInfo { name: v.name.clone(), size: v.data.len() as u64 }).collect(),
+    }, &attachments, if context.compression.store_compressed { codec } else { Codec::None })?;
+    trace!("Successfully saved report {} of '{}'", uuid, app.name);
+    if bucket.count > 1 {
+        info!("Report {} of '{}' is a known crash, bucket {} has {} reports since {}", uuid, app.name, bucket.id, bucket.count, bucket.first_seen);
+    } else {
+        info!("Report {} of '{}' is a new crash, created bucket {}", uuid, app.name, bucket.id);
+    }
 
     rpr_proto::send_message(&mut stream, ServerMessage::ReportReceived {
-        report_id: uuid.as_u128()
+        report_id: uuid.as_u128(),
+        occurrences: bucket.count,
     })?;
     stream.shutdown(Shutdown::Both)?;
-    let mut file = File::create(format!("{}/{}-{}.txt", report_path, app.name, uuid))?;
-    file.write_all(&report)?;
-    trace!("Successfully saved report {}-{}.txt", app.name, uuid);
 
     Ok(())
-}
\ No newline at end of file
+}
+
+// The limits are checked before the upload starts, so the client doesn't send data that is refused anyway
+fn check_attachments(attachments: &[AttachmentHeader], limits: &Limits) -> Result<(), String> {
+    if attachments.len() > limits.max_attachments as usize {
+        return Err(format!("{} attachments, the limit is {}", attachments.len(), limits.max_attachments));
+    }
+    for (idx, i) in attachments.iter().enumerate() {
+        rpr_proto::check_attachment_name(&i.name).map_err(|e| format!("attachment name '{}' {}", i.name, e))?;
+        if attachments[..idx].iter().any(|v| v.name == i.name) {
+            return Err(format!("attachment name '{}' is used twice", i.name));
+        }
+        if i.size > limits.max_attachment_size {
+            return Err(format!("attachment '{}' is bigger than the limit of {} bytes", i.name, limits.max_attachment_size));
+        }
+    }
+    Ok(())
+}
+
+fn new_report_id() -> Uuid {
+    Uuid::from_u64_pair(rand::thread_rng().next_u64(), rand::thread_rng().next_u64())
+}
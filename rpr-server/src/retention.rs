@@ -0,0 +1,51 @@
+use std::time::Duration;
+use log::{error, info, trace};
+use crate::application::Applications;
+use crate::storage::{self, ReportFilter, Storage};
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Periodically runs `purge`, which deletes the reports that are past their application's retention period
+pub fn spawn_purge_task<F>(purge: F)
+where
+    F: Fn() + Send + 'static,
+{
+    std::thread::spawn(move || loop {
+        purge();
+        std::thread::sleep(PURGE_INTERVAL);
+    });
+}
+
+pub fn retention_periods(applications: &Applications) -> Vec<(String, u32)> {
+    applications.values()
+        .filter_map(|v| v.retention_days.map(|days| (v.name.clone(), days)))
+        .collect()
+}
+
+pub fn purge_reports(storage: &Storage, app: &str, days: u32) {
+    let cutoff = storage::now().saturating_sub(days as u64 * 24 * 60 * 60);
+    let filter = ReportFilter {
+        app: Some(app.to_string()),
+        until: Some(cutoff.saturating_sub(1)),
+        ..Default::default()
+    };
+    let expired = match storage.query(&filter) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Failed to list reports of '{}' for purging: {}", app, e);
+            return;
+        }
+    };
+
+    let mut count = 0;
+    for report in expired {
+        trace!("Deleting expired report {}", report.id);
+        match storage.delete(report.id) {
+            Ok(_) => count += 1,
+            Err(e) => error!("Failed to delete expired report {}: {}", report.id, e),
+        }
+    }
+    if count > 0 {
+        info!("Deleted {} reports of '{}' older than {} days", count, app, days);
+    }
+}
@@ -0,0 +1,139 @@
+use sha2::{Digest, Sha256};
+use rpr_proto::CrashReport;
+
+// Frames of the application itself that go into the signature, counted from where the panic happened
+const SIGNATURE_FRAMES: usize = 5;
+
+// Symbols of the standard library, the reporter and the platform's startup code, these are the same for every crash
+const IGNORED_PREFIXES: &[&str] = &[
+    "std::", "core::", "alloc::", "backtrace::", "rpr::", "rpr_proto::",
+    "rust_begin_unwind", "rust_panic", "__rust", "__libc_start", "_start", "__scrt_common_main",
+    "BaseThreadInitThunk", "RtlUserThreadStart",
+];
+// The C entry point, the application's own main is always inside a crate
+const IGNORED_NAMES: &[&str] = &["main"];
+
+// Identifies reports of the same crash, based on where the panic happened and how the application got there.
+// Frames only contribute their function names, so rebuilding doesn't create a new signature. The panic's own
+// location includes its line though, so an edit that moves the panic does.
+pub fn crash_signature(report: &CrashReport) -> String {
+    let mut hasher = Sha256::new();
+    match &report.location {
+        Some(v) => hasher.update(format!("{}:{}", v.file, v.line)),
+        // without a location the message is the best there is
+        None => hasher.update(report.message.as_deref().unwrap_or("")),
+    }
+
+    for name in application_frames(report).take(SIGNATURE_FRAMES) {
+        hasher.update([0]);
+        hasher.update(&name);
+    }
+
+    hasher.finalize()[..8].iter().map(|v| format!("{:02x}", v)).collect()
+}
+
+fn application_frames(report: &CrashReport) -> impl Iterator<Item = String> + '_ {
+    report.backtrace.iter()
+        .flat_map(|v| v.symbols.iter())
+        .filter_map(|v| v.name.as_deref())
+        .map(normalize_symbol)
+        .filter(|name| {
+            // trait implementations look like <alloc::boxed::Box<F> as core::ops::Fn>::call or <&dyn core::ops::Fn>::call
+            let trimmed = name.trim_start_matches(['<', '&']).trim_start_matches("mut ").trim_start_matches("dyn ");
+            !IGNORED_PREFIXES.iter().any(|v| trimmed.starts_with(v)) && !IGNORED_NAMES.contains(&name.as_str())
+        })
+}
+
+// Removes what changes with every build: the hash legacy mangling appends, e.g. app::main::h1a2b3c4d5e6f7a8b,
+// and the crate disambiguators of v0 mangling, e.g. std[e28293b1aa0f68bd]::panicking
+fn normalize_symbol(name: &str) -> String {
+    let name = match name.rsplit_once("::h") {
+        Some((path, hash)) if hash.len() == 16 && hash.chars().all(|v| v.is_ascii_hexdigit()) => path,
+        _ => name,
+    };
+
+    let mut normalized = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find('[') {
+        normalized.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        match after.find(']') {
+            Some(end) if end > 0 && after[..end].chars().all(|v| v.is_ascii_hexdigit()) => rest = &after[end + 1..],
+            _ => {
+                normalized.push('[');
+                rest = after;
+            },
+        }
+    }
+    normalized.push_str(rest);
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rpr_proto::{Frame, Location, Symbol};
+
+    fn report(location: Option<(&str, u32)>, symbols: &[&str]) -> CrashReport {
+        CrashReport {
+            location: location.map(|(file, line)| Location { file: file.to_string(), line, column: 5 }),
+            backtrace: symbols.iter().enumerate().map(|(ip, name)| Frame {
+                ip: ip as u64,
+                symbols: vec![Symbol { name: Some(name.to_string()), file: None, line: None }],
+            }).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn legacy_hashes() {
+        assert_eq!(normalize_symbol("app::main::h1a2b3c4d5e6f7a8b"), "app::main");
+        assert_eq!(normalize_symbol("app::config::load::h0123456789abcdef"), "app::config::load");
+        // not a hash
+        assert_eq!(normalize_symbol("app::handler::hello"), "app::handler::hello");
+        assert_eq!(normalize_symbol("app::h1a2b"), "app::h1a2b");
+    }
+
+    #[test]
+    fn v0_disambiguators() {
+        assert_eq!(normalize_symbol("std[e28293b1aa0f68bd]::panicking::begin_panic"), "std::panicking::begin_panic");
+        assert_eq!(normalize_symbol("<app[1a2b]::Game as core[9f8e]::ops::Drop>::drop"), "<app::Game as core::ops::Drop>::drop");
+        // arrays and slices aren't disambiguators
+        assert_eq!(normalize_symbol("<[u8] as app::Parse>::parse"), "<[u8] as app::Parse>::parse");
+        assert_eq!(normalize_symbol("app::table::<[_]>::get"), "app::table::<[_]>::get");
+    }
+
+    #[test]
+    fn application_frames_only() {
+        let report = report(Some(("src/main.rs", 10)), &[
+            "backtrace::backtrace::trace::h0123456789abcdef",
+            "rpr_proto::report::generate_report::h0123456789abcdef",
+            "rpr::panic_hook::h0123456789abcdef",
+            "std::panicking::rust_panic_with_hook",
+            "core::panicking::panic_fmt",
+            "app::config::load::h0123456789abcdef",
+            "<app::Game as app::Update>::update",
+            "<alloc::boxed::Box<F> as core::ops::function::Fn<A>>::call",
+            "<&dyn core::ops::function::Fn<()>>::call",
+            "app::main",
+            "std::rt::lang_start_internal",
+            "main",
+            "__libc_start_main",
+            "_start",
+        ]);
+        assert_eq!(application_frames(&report).collect::<Vec<_>>(), ["app::config::load", "<app::Game as app::Update>::update", "app::main"]);
+    }
+
+    #[test]
+    fn signature_ignores_rebuilds() {
+        let first = report(Some(("src/main.rs", 10)), &["std::panicking::begin_panic", "app::run::h0123456789abcdef", "app::main::h0123456789abcdef"]);
+        let rebuilt = report(Some(("src/main.rs", 10)), &["std::panicking::begin_panic", "app::run::hfedcba9876543210", "app::main::hfedcba9876543210"]);
+        assert_eq!(crash_signature(&first), crash_signature(&rebuilt));
+        assert_eq!(crash_signature(&first).len(), 16);
+
+        let moved = report(Some(("src/main.rs", 12)), &["app::run", "app::main"]);
+        assert_ne!(crash_signature(&first), crash_signature(&moved));
+        let other_path = report(Some(("src/main.rs", 10)), &["app::other", "app::main"]);
+        assert_ne!(crash_signature(&first), crash_signature(&other_path));
+    }
+}
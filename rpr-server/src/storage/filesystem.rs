@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::Result;
+use log::warn;
+use rpr_proto::Codec;
+use crate::application::check_application_name;
+use super::{Attachment, ReportStore, ReportSummary, StoredReport};
+
+// Stores every report as {root}/{app}/{id}.json, with a plain text rendering next to it.
+// Compressed reports get the codec's extension appended and no plain text rendering, that would defeat the point.
+// Attachments go into {root}/{app}/{id}.attachments/
+pub struct FilesystemStore {
+    root: PathBuf,
+}
+
+impl FilesystemStore {
+    pub fn new(root: &Path) -> Result<Self> {
+        Ok(Self {
+            root: root.canonicalize()?,
+        })
+    }
+
+    fn app_dir(&self, app: &str) -> Result<PathBuf> {
+        check_application_name(app)?;
+        Ok(self.root.join(app))
+    }
+
+    fn find(&self, report: &ReportSummary) -> Result<Option<PathBuf>> {
+        let dir = self.app_dir(&report.app)?;
+        for codec in [Codec::None, Codec::Zstd, Codec::Deflate] {
+            let path = dir.join(format!("{}.json{}", report.id, codec.extension()));
+            if path.is_file() {
+                return Ok(Some(path));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl ReportStore for FilesystemStore {
+    fn store(&self, report: &StoredReport, attachments: &[Attachment], codec: Codec) -> Result<()> {
+        let dir = self.app_dir(&report.app)?;
+        fs::create_dir_all(&dir)?;
+        if !attachments.is_empty() {
+            let attachment_dir = dir.join(format!("{}.attachments", report.id));
+            fs::create_dir_all(&attachment_dir)?;
+            for i in attachments {
+                fs::write(attachment_dir.join(&i.name), &i.data)?;
+            }
+        }
+        if codec != Codec::None {
+            fs::write(dir.join(format!("{}.json{}", report.id, codec.extension())), super::encode(report, codec)?)?;
+            return Ok(());
+        }
+        fs::write(dir.join(format!("{}.json", report.id)), serde_json::to_vec_pretty(report)?)?;
+        fs::write(dir.join(format!("{}.txt", report.id)), report.report.to_string())?;
+        Ok(())
+    }
+
+    fn load(&self, report: &ReportSummary) -> Result<Option<StoredReport>> {
+        match self.find(report)? {
+            Some(path) => Ok(Some(super::decode(&fs::read(path)?)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_attachment(&self, report: &ReportSummary, name: &str) -> Result<Option<Vec<u8>>> {
+        let path = match self.find(report)? {
+            Some(v) => v.with_file_name(format!("{}.attachments", report.id)).join(name),
+            None => return Ok(None),
+        };
+        match fs::read(path) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn list(&self, app: Option<&str>) -> Result<Vec<ReportSummary>> {
+        let dirs = match app {
+            Some(app) => vec![self.app_dir(app)?],
+            None => fs::read_dir(&self.root)?
+                .filter_map(|i| i.ok())
+                .map(|i| i.path())
+                .filter(|i| i.is_dir())
+                .collect(),
+        };
+
+        let mut reports = vec![];
+        for dir in dirs.iter().filter(|v| v.is_dir()) {
+            for i in fs::read_dir(dir)? {
+                let path = i?.path();
+                if !path.file_name().is_some_and(|v| v.to_string_lossy().contains(".json")) {
+                    continue;
+                }
+                match fs::read(&path).map_err(anyhow::Error::from).and_then(|v| super::decode(&v)) {
+                    Ok(v) => reports.push(v.summary()),
+                    Err(e) => warn!("Skipping unreadable report '{}': {}", path.to_string_lossy(), e),
+                }
+            }
+        }
+        reports.sort_by_key(|v| std::cmp::Reverse(v.received_at));
+
+        Ok(reports)
+    }
+
+    fn delete(&self, report: &ReportSummary) -> Result<bool> {
+        let path = match self.find(report)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        fs::remove_file(&path)?;
+        let _ = fs::remove_file(path.with_file_name(format!("{}.txt", report.id)));
+        let _ = fs::remove_dir_all(path.with_file_name(format!("{}.attachments", report.id)));
+        Ok(true)
+    }
+}
@@ -0,0 +1,247 @@
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::Result;
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::Serialize;
+use uuid::Uuid;
+use crate::signature::crash_signature;
+use super::{ReportSummary, StoredReport};
+
+// Bumped whenever the tables change, older tables are dropped and rebuilt from the stored reports
+const SCHEMA_VERSION: i64 = 2;
+
+// Searchable metadata of every stored report, the reports themselves stay in the configured backend
+pub struct ReportIndex {
+    conn: Mutex<Connection>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct IndexEntry {
+    pub id: Uuid,
+    pub app: String,
+    pub received_at: u64,
+    pub peer: String,
+    pub client_version: String,
+    pub os: String,
+    pub os_version: String,
+    pub message: Option<String>,
+    pub location: Option<String>,
+    pub signature: String,
+    // Set once the entry is in the index
+    pub bucket: Option<i64>,
+}
+
+// All reports of an application with the same crash signature
+#[derive(Serialize, Clone, Debug)]
+pub struct Bucket {
+    pub id: i64,
+    pub app: String,
+    pub signature: String,
+    pub count: u64,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    // Taken from the first report in the bucket
+    pub message: Option<String>,
+    pub location: Option<String>,
+}
+
+// Every field that is set has to match, `since` and `until` are inclusive
+#[derive(Default, Clone, Debug)]
+pub struct ReportFilter {
+    pub app: Option<String>,
+    // Compared case insensitively, e.g. "windows"
+    pub os: Option<String>,
+    pub since: Option<u64>,
+    pub until: Option<u64>,
+    pub signature: Option<String>,
+    pub bucket: Option<i64>,
+    pub limit: Option<usize>,
+}
+
+impl IndexEntry {
+    pub fn new(report: &StoredReport) -> Self {
+        Self {
+            id: report.id,
+            app: report.app.clone(),
+            received_at: report.received_at,
+            peer: report.peer.clone(),
+            client_version: report.client_version.clone(),
+            os: report.report.os.os_type.clone(),
+            os_version: report.report.os.version.clone(),
+            message: report.report.message.clone(),
+            location: report.report.location.as_ref().map(|v| v.to_string()),
+            signature: crash_signature(&report.report),
+            bucket: None,
+        }
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let id: String = row.get(0)?;
+        Ok(Self {
+            id: Uuid::parse_str(&id).map_err(|e| rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(e)))?,
+            app: row.get(1)?,
+            received_at: row.get::<_, i64>(2)? as u64,
+            peer: row.get(3)?,
+            client_version: row.get(4)?,
+            os: row.get(5)?,
+            os_version: row.get(6)?,
+            message: row.get(7)?,
+            location: row.get(8)?,
+            signature: row.get(9)?,
+            bucket: row.get(10)?,
+        })
+    }
+}
+
+impl Bucket {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get(0)?,
+            app: row.get(1)?,
+            signature: row.get(2)?,
+            count: row.get::<_, i64>(3)? as u64,
+            first_seen: row.get::<_, i64>(4)? as u64,
+            last_seen: row.get::<_, i64>(5)? as u64,
+            message: row.get(6)?,
+            location: row.get(7)?,
+        })
+    }
+}
+
+impl ReportIndex {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            if version != 0 {
+                info!("Index database '{}' is outdated, rebuilding it", path.display());
+            }
+            conn.execute_batch("DROP TABLE IF EXISTS report_index; DROP TABLE IF EXISTS buckets;")?;
+        }
+
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS buckets (
+                id INTEGER PRIMARY KEY,
+                app TEXT NOT NULL,
+                signature TEXT NOT NULL,
+                count INTEGER NOT NULL,
+                first_seen INTEGER NOT NULL,
+                last_seen INTEGER NOT NULL,
+                message TEXT,
+                location TEXT,
+                UNIQUE (app, signature)
+            );
+            CREATE TABLE IF NOT EXISTS report_index (
+                id TEXT PRIMARY KEY,
+                app TEXT NOT NULL,
+                received_at INTEGER NOT NULL,
+                peer TEXT NOT NULL,
+                client_version TEXT NOT NULL,
+                os TEXT NOT NULL,
+                os_version TEXT NOT NULL,
+                message TEXT,
+                location TEXT,
+                signature TEXT NOT NULL,
+                bucket INTEGER NOT NULL REFERENCES buckets (id)
+            );
+            CREATE INDEX IF NOT EXISTS report_index_app_received_at ON report_index (app, received_at);
+            CREATE INDEX IF NOT EXISTS report_index_os_received_at ON report_index (os COLLATE NOCASE, received_at);
+            CREATE INDEX IF NOT EXISTS report_index_signature ON report_index (signature);
+            CREATE INDEX IF NOT EXISTS report_index_bucket ON report_index (bucket, received_at);"
+        )?;
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    // Adds the report to its bucket, creating the bucket for a new signature, and returns the updated bucket
+    pub fn insert(&self, entry: &IndexEntry) -> Result<Bucket> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+
+        let bucket = transaction.query_row(
+            "INSERT INTO buckets (app, signature, count, first_seen, last_seen, message, location)
+            VALUES (?1, ?2, 1, ?3, ?3, ?4, ?5)
+            ON CONFLICT (app, signature) DO UPDATE SET
+                count = count + 1,
+                first_seen = MIN(first_seen, excluded.first_seen),
+                last_seen = MAX(last_seen, excluded.last_seen)
+            RETURNING id, app, signature, count, first_seen, last_seen, message, location",
+            params![entry.app, entry.signature, entry.received_at as i64, entry.message, entry.location],
+            Bucket::from_row,
+        )?;
+        transaction.execute(
+            "INSERT INTO report_index (id, app, received_at, peer, client_version, os, os_version, message, location, signature, bucket)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                entry.id.to_string(), entry.app, entry.received_at as i64, entry.peer, entry.client_version,
+                entry.os, entry.os_version, entry.message, entry.location, entry.signature, bucket.id,
+            ],
+        )?;
+
+        transaction.commit()?;
+        Ok(bucket)
+    }
+
+    // The report's bucket keeps its count, it counts occurrences rather than the reports still stored
+    pub fn remove(&self, id: Uuid) -> Result<bool> {
+        let deleted = self.conn.lock().unwrap().execute("DELETE FROM report_index WHERE id = ?1", params![id.to_string()])?;
+        Ok(deleted > 0)
+    }
+
+    pub fn summary(&self, id: Uuid) -> Result<Option<ReportSummary>> {
+        Ok(self.conn.lock().unwrap().query_row(
+            "SELECT app, received_at FROM report_index WHERE id = ?1",
+            params![id.to_string()],
+            |row| Ok(ReportSummary { id, app: row.get(0)?, received_at: row.get::<_, i64>(1)? as u64 }),
+        ).optional()?)
+    }
+
+    pub fn count(&self) -> Result<u64> {
+        let count: i64 = self.conn.lock().unwrap().query_row("SELECT COUNT(*) FROM report_index", [], |row| row.get(0))?;
+        Ok(count as u64)
+    }
+
+    // Newest first
+    pub fn query(&self, filter: &ReportFilter) -> Result<Vec<IndexEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, app, received_at, peer, client_version, os, os_version, message, location, signature, bucket FROM report_index
+            WHERE (?1 IS NULL OR app = ?1)
+                AND (?2 IS NULL OR os = ?2 COLLATE NOCASE)
+                AND (?3 IS NULL OR received_at >= ?3)
+                AND (?4 IS NULL OR received_at <= ?4)
+                AND (?5 IS NULL OR signature = ?5)
+                AND (?6 IS NULL OR bucket = ?6)
+            ORDER BY received_at DESC
+            LIMIT ?7"
+        )?;
+        let rows = statement.query_map(
+            params![
+                filter.app, filter.os, filter.since.map(|v| v as i64), filter.until.map(|v| v as i64), filter.signature,
+                filter.bucket, filter.limit.map(|v| v as i64).unwrap_or(-1),
+            ],
+            IndexEntry::from_row,
+        )?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+
+    // Most recently seen first, all applications when `app` is None
+    pub fn buckets(&self, app: Option<&str>) -> Result<Vec<Bucket>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, app, signature, count, first_seen, last_seen, message, location FROM buckets
+            WHERE ?1 IS NULL OR app = ?1
+            ORDER BY last_seen DESC"
+        )?;
+        let rows = statement.query_map(params![app], Bucket::from_row)?;
+
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
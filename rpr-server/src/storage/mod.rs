@@ -0,0 +1,206 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use rpr_proto::{Codec, CrashReport};
+use crate::config::StorageConfig;
+
+pub mod filesystem;
+pub mod index;
+pub mod s3;
+pub mod sqlite;
+
+pub use filesystem::FilesystemStore;
+pub use index::{Bucket, IndexEntry, ReportFilter, ReportIndex};
+pub use s3::S3Store;
+pub use sqlite::SqliteStore;
+
+// A report together with what the server knows about its submission
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredReport {
+    pub id: Uuid,
+    pub app: String,
+    // Seconds since the UNIX epoch
+    pub received_at: u64,
+    pub peer: String,
+    // Empty for reports stored before the version was recorded
+    #[serde(default)]
+    pub client_version: String,
+    pub report: CrashReport,
+    // Names and sizes only, the data is kept by the backend next to the report
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AttachmentInfo {
+    pub name: String,
+    pub size: u64,
+}
+
+// A file the client sent along with the report, stored as received after decompressing it
+pub struct Attachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReportSummary {
+    pub id: Uuid,
+    pub app: String,
+    pub received_at: u64,
+}
+
+// Stored reports are bigger than what was received, but anything past this is not a report
+const MAX_STORED_SIZE: usize = 256 * 1024 * 1024;
+
+pub trait ReportStore: Send + Sync {
+    // `codec` is what the report is compressed with in storage, loading recognizes it by itself.
+    // The attachments are the ones listed in the report, they have to be stored before the report or in the same
+    // transaction so that a report that can be loaded always has its attachments.
+    fn store(&self, report: &StoredReport, attachments: &[Attachment], codec: Codec) -> Result<()>;
+    // `report` comes from the index or list(), so a backend can go straight to where it stored the report
+    fn load(&self, report: &ReportSummary) -> Result<Option<StoredReport>>;
+    fn load_attachment(&self, report: &ReportSummary, name: &str) -> Result<Option<Vec<u8>>>;
+    // Newest first, all applications when `app` is None
+    fn list(&self, app: Option<&str>) -> Result<Vec<ReportSummary>>;
+    // Returns false if there was no such report, the attachments are deleted with it
+    fn delete(&self, report: &ReportSummary) -> Result<bool>;
+}
+
+impl StoredReport {
+    pub fn summary(&self) -> ReportSummary {
+        ReportSummary {
+            id: self.id,
+            app: self.app.clone(),
+            received_at: self.received_at,
+        }
+    }
+}
+
+pub fn open(config: &StorageConfig) -> Result<Box<dyn ReportStore>> {
+    Ok(match config {
+        StorageConfig::Filesystem { path } => Box::new(FilesystemStore::new(path)?),
+        StorageConfig::Sqlite { path } => Box::new(SqliteStore::open(path)?),
+        StorageConfig::S3 { endpoint, bucket, region, access_key, secret_key, prefix, path_style } => {
+            let access_key = access_key.clone().or(std::env::var("AWS_ACCESS_KEY_ID").ok());
+            let secret_key = secret_key.clone().or(std::env::var("AWS_SECRET_ACCESS_KEY").ok());
+            let (access_key, secret_key) = match (access_key, secret_key) {
+                (Some(a), Some(s)) => (a, s),
+                _ => anyhow::bail!("S3 storage requires access_key and secret_key, or AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"),
+            };
+            Box::new(S3Store::new(endpoint, bucket, region, &access_key, &secret_key, prefix, *path_style)?)
+        },
+    })
+}
+
+// The configured backend together with the metadata index, keeps both in sync
+pub struct Storage {
+    store: Box<dyn ReportStore>,
+    index: ReportIndex,
+}
+
+impl Storage {
+    pub fn open(config: &StorageConfig, index_path: Option<&Path>) -> Result<Self> {
+        let index_path = match index_path {
+            Some(v) => v.to_path_buf(),
+            None => default_index_path(config),
+        };
+        let storage = Self {
+            store: open(config)?,
+            index: ReportIndex::open(&index_path)?,
+        };
+
+        // reports stored before the index existed, or after it was deleted
+        if storage.index.count()? == 0 {
+            let reindexed = storage.reindex()?;
+            if reindexed > 0 {
+                info!("Indexed {} existing reports into '{}'", reindexed, index_path.display());
+            }
+        }
+        Ok(storage)
+    }
+
+    // Returns the bucket the report was added to
+    pub fn store(&self, report: &StoredReport, attachments: &[Attachment], codec: Codec) -> Result<Bucket> {
+        self.store.store(report, attachments, codec)?;
+        self.index.insert(&IndexEntry::new(report))
+    }
+
+    // Only finds indexed reports, the index says where the backend keeps them
+    pub fn load(&self, id: Uuid) -> Result<Option<StoredReport>> {
+        match self.index.summary(id)? {
+            Some(v) => self.store.load(&v),
+            None => Ok(None),
+        }
+    }
+
+    pub fn load_attachment(&self, id: Uuid, name: &str) -> Result<Option<Vec<u8>>> {
+        // names that aren't valid can't have been stored, and may not be safe to pass on to the backend
+        if rpr_proto::check_attachment_name(name).is_err() {
+            return Ok(None);
+        }
+        match self.index.summary(id)? {
+            Some(v) => self.store.load_attachment(&v, name),
+            None => Ok(None),
+        }
+    }
+
+    pub fn query(&self, filter: &ReportFilter) -> Result<Vec<IndexEntry>> {
+        self.index.query(filter)
+    }
+
+    pub fn buckets(&self, app: Option<&str>) -> Result<Vec<Bucket>> {
+        self.index.buckets(app)
+    }
+
+    pub fn delete(&self, id: Uuid) -> Result<bool> {
+        let summary = match self.index.summary(id)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+        self.store.delete(&summary)?;
+        self.index.remove(id)
+    }
+
+    // Adds every report in the backend to the index, returns how many there were
+    pub fn reindex(&self) -> Result<usize> {
+        let mut count = 0;
+        for summary in self.store.list(None)? {
+            match self.store.load(&summary) {
+                Ok(Some(v)) => {
+                    self.index.insert(&IndexEntry::new(&v))?;
+                    count += 1;
+                },
+                Ok(None) => (),
+                Err(e) => warn!("Skipping unreadable report {}: {}", summary.id, e),
+            }
+        }
+        Ok(count)
+    }
+}
+
+// The SQLite backend keeps the index in its own database, the others next to the reports or in the working directory
+fn default_index_path(config: &StorageConfig) -> PathBuf {
+    match config {
+        StorageConfig::Filesystem { path } => path.join("index.sqlite"),
+        StorageConfig::Sqlite { path } => path.clone(),
+        StorageConfig::S3 { .. } => PathBuf::from("index.sqlite"),
+    }
+}
+
+// The JSON document of the report, compressed with `codec`
+pub fn encode(report: &StoredReport, codec: Codec) -> Result<Vec<u8>> {
+    codec.compress(&serde_json::to_vec(report)?)
+}
+
+pub fn decode(data: &[u8]) -> Result<StoredReport> {
+    let data = Codec::detect(data).decompress(data, MAX_STORED_SIZE)?;
+    Ok(serde_json::from_slice(&data)?)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|v| v.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,268 @@
+use std::io::Read;
+use std::time::Duration;
+use anyhow::Result;
+use rusty_s3::{Bucket, Credentials, S3Action, UrlStyle};
+use rusty_s3::actions::ListObjectsV2;
+use url::Url;
+use uuid::Uuid;
+use rpr_proto::Codec;
+use super::{Attachment, ReportStore, ReportSummary, StoredReport};
+
+const SIGNATURE_VALIDITY: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Stores reports as {prefix}{app}/{received_at}-{id}.json in an S3 compatible bucket (AWS, MinIO, ...), with .zst or .zz
+// appended when compressed, and their attachments under {prefix}{app}/{received_at}-{id}.attachments/.
+// Putting the app and time in the key lets listing work without downloading every report, and lets an index entry
+// lead straight to its report
+pub struct S3Store {
+    bucket: Bucket,
+    credentials: Credentials,
+    prefix: String,
+    agent: ureq::Agent,
+}
+
+impl S3Store {
+    pub fn new(endpoint: &str, bucket: &str, region: &str, access_key: &str, secret_key: &str, prefix: &str, path_style: bool) -> Result<Self> {
+        let url_style = if path_style { UrlStyle::Path } else { UrlStyle::VirtualHost };
+        Ok(Self {
+            bucket: Bucket::new(Url::parse(endpoint)?, url_style, bucket.to_string(), region.to_string())?,
+            credentials: Credentials::new(access_key, secret_key),
+            prefix: prefix.to_string(),
+            agent: ureq::AgentBuilder::new().timeout(REQUEST_TIMEOUT).build(),
+        })
+    }
+
+    fn key(&self, report: &ReportSummary, codec: Codec) -> String {
+        format!("{}{}/{:012}-{}.json{}", self.prefix, report.app, report.received_at, report.id, codec.extension())
+    }
+
+    // Where the attachments of the report are
+    fn attachment_prefix(&self, report: &ReportSummary) -> String {
+        format!("{}{}/{:012}-{}.attachments/", self.prefix, report.app, report.received_at, report.id)
+    }
+
+    fn parse_key(&self, key: &str) -> Option<ReportSummary> {
+        let (app, name) = key.strip_prefix(&self.prefix)?.split_once('/')?;
+        if name.contains('/') {
+            // an attachment
+            return None;
+        }
+        let (name, _) = name.split_once(".json")?;
+        let (received_at, id) = name.split_once('-')?;
+        Some(ReportSummary {
+            id: Uuid::parse_str(id).ok()?,
+            app: app.to_string(),
+            received_at: received_at.parse().ok()?,
+        })
+    }
+
+    fn list_keys(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut keys = vec![];
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut action = ListObjectsV2::new(&self.bucket, Some(&self.credentials));
+            action.with_prefix(prefix);
+            if let Some(token) = &continuation_token {
+                action.with_continuation_token(token.as_str());
+            }
+
+            let body = self.agent.get(action.sign(SIGNATURE_VALIDITY).as_str()).call()?.into_string()?;
+            let response = ListObjectsV2::parse_response(&body)?;
+            keys.extend(response.contents.into_iter().map(|v| v.key));
+
+            match response.next_continuation_token {
+                Some(v) => continuation_token = Some(v),
+                None => break,
+            }
+        }
+        Ok(keys)
+    }
+
+    // The key is known up to the codec's extension, so listing it as a prefix only returns this report
+    fn find(&self, report: &ReportSummary) -> Result<Option<String>> {
+        Ok(self.list_keys(&self.key(report, Codec::None))?.into_iter().find(|v| self.parse_key(v).is_some()))
+    }
+}
+
+impl ReportStore for S3Store {
+    fn store(&self, report: &StoredReport, attachments: &[Attachment], codec: Codec) -> Result<()> {
+        let key = self.key(&report.summary(), codec);
+        for i in attachments {
+            let attachment_key = format!("{}{}", self.attachment_prefix(&report.summary()), i.name);
+            let action = self.bucket.put_object(Some(&self.credentials), &attachment_key);
+            self.agent.put(action.sign(SIGNATURE_VALIDITY).as_str())
+                .set("Content-Type", "application/octet-stream")
+                .send_bytes(&i.data)?;
+        }
+
+        let content_type = match codec {
+            Codec::None => "application/json",
+            _ => "application/octet-stream",
+        };
+        let action = self.bucket.put_object(Some(&self.credentials), &key);
+        self.agent.put(action.sign(SIGNATURE_VALIDITY).as_str())
+            .set("Content-Type", content_type)
+            .send_bytes(&super::encode(report, codec)?)?;
+        Ok(())
+    }
+
+    fn load(&self, report: &ReportSummary) -> Result<Option<StoredReport>> {
+        let key = match self.find(report)? {
+            Some(v) => v,
+            None => return Ok(None),
+        };
+
+        let action = self.bucket.get_object(Some(&self.credentials), &key);
+        let mut data = vec![];
+        self.agent.get(action.sign(SIGNATURE_VALIDITY).as_str()).call()?.into_reader().read_to_end(&mut data)?;
+        Ok(Some(super::decode(&data)?))
+    }
+
+    fn load_attachment(&self, report: &ReportSummary, name: &str) -> Result<Option<Vec<u8>>> {
+        let key = format!("{}{}", self.attachment_prefix(report), name);
+        let action = self.bucket.get_object(Some(&self.credentials), &key);
+        let response = match self.agent.get(action.sign(SIGNATURE_VALIDITY).as_str()).call() {
+            Ok(v) => v,
+            Err(ureq::Error::Status(404, _)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut data = vec![];
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(Some(data))
+    }
+
+    fn list(&self, app: Option<&str>) -> Result<Vec<ReportSummary>> {
+        let prefix = match app {
+            Some(app) => format!("{}{}/", self.prefix, app),
+            None => self.prefix.clone(),
+        };
+
+        let mut reports: Vec<_> = self.list_keys(&prefix)?.iter().filter_map(|v| self.parse_key(v)).collect();
+        reports.sort_by_key(|v| std::cmp::Reverse(v.received_at));
+        Ok(reports)
+    }
+
+    fn delete(&self, report: &ReportSummary) -> Result<bool> {
+        let key = match self.find(report)? {
+            Some(v) => v,
+            None => return Ok(false),
+        };
+
+        for i in self.list_keys(&self.attachment_prefix(report))? {
+            let action = self.bucket.delete_object(Some(&self.credentials), &i);
+            self.agent.delete(action.sign(SIGNATURE_VALIDITY).as_str()).call()?;
+        }
+        let action = self.bucket.delete_object(Some(&self.credentials), &key);
+        self.agent.delete(action.sign(SIGNATURE_VALIDITY).as_str()).call()?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use rpr_proto::CrashReport;
+    use tiny_http::{Method, Response, Server};
+
+    // Just enough of MinIO for S3Store: path style PUT, GET, DELETE and ListObjectsV2, signatures aren't checked.
+    // Remembers the prefix of every listing.
+    struct FakeS3 {
+        objects: Mutex<BTreeMap<String, Vec<u8>>>,
+        listings: Mutex<Vec<String>>,
+    }
+
+    impl FakeS3 {
+        fn start() -> (Arc<FakeS3>, String) {
+            let server = Server::http("127.0.0.1:0").unwrap();
+            let endpoint = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let fake = Arc::new(FakeS3 { objects: Mutex::new(BTreeMap::new()), listings: Mutex::new(vec![]) });
+            let state = fake.clone();
+            std::thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let response = state.handle(&mut request);
+                    let _ = request.respond(response);
+                }
+            });
+            (fake, endpoint)
+        }
+
+        fn handle(&self, request: &mut tiny_http::Request) -> Response<std::io::Cursor<Vec<u8>>> {
+            let url = Url::parse(&format!("http://localhost{}", request.url())).unwrap();
+            let key = url.path().trim_start_matches("/reports/").to_string();
+            let mut objects = self.objects.lock().unwrap();
+            match request.method() {
+                Method::Put => {
+                    let mut data = vec![];
+                    request.as_reader().read_to_end(&mut data).unwrap();
+                    objects.insert(key, data);
+                    Response::from_data(vec![])
+                },
+                Method::Get if url.query_pairs().any(|(k, _)| k == "list-type") => {
+                    let prefix = url.query_pairs().find(|(k, _)| k == "prefix").map(|(_, v)| v.to_string()).unwrap_or_default();
+                    let contents: String = objects.iter()
+                        .filter(|(k, _)| k.starts_with(&prefix))
+                        .map(|(k, v)| format!("<Contents><Key>{}</Key><LastModified>2024-01-01T00:00:00.000Z</LastModified><ETag>\"x\"</ETag><Size>{}</Size></Contents>", k, v.len()))
+                        .collect();
+                    self.listings.lock().unwrap().push(prefix);
+                    Response::from_data(format!("<ListBucketResult xmlns=\"http://s3.amazonaws.com/doc/2006-03-01/\">{}</ListBucketResult>", contents).into_bytes())
+                },
+                Method::Get => match objects.get(&key) {
+                    Some(v) => Response::from_data(v.clone()),
+                    None => Response::from_data(vec![]).with_status_code(404),
+                },
+                Method::Delete => {
+                    objects.remove(&key);
+                    Response::from_data(vec![]).with_status_code(204)
+                },
+                _ => Response::from_data(vec![]).with_status_code(405),
+            }
+        }
+    }
+
+    fn report(app: &str, received_at: u64) -> StoredReport {
+        StoredReport {
+            id: Uuid::from_u128(rand::random()),
+            app: app.to_string(),
+            received_at,
+            peer: "127.0.0.1:1234".to_string(),
+            client_version: "0.1.0".to_string(),
+            report: CrashReport { timestamp: received_at, ..Default::default() },
+            attachments: vec![],
+        }
+    }
+
+    #[test]
+    fn store_load_and_delete() {
+        let (fake, endpoint) = FakeS3::start();
+        let store = S3Store::new(&endpoint, "reports", "us-east-1", "minio", "minio123", "crash/", true).unwrap();
+
+        let first = report("game", 1000);
+        let second = report("editor", 2000);
+        let attachments = [Attachment { name: "app.log".to_string(), data: b"log lines".to_vec() }];
+        store.store(&first, &attachments, Codec::Zstd).unwrap();
+        store.store(&second, &[], Codec::None).unwrap();
+
+        let listed = store.list(None).unwrap();
+        assert_eq!(listed.iter().map(|v| v.id).collect::<Vec<_>>(), [second.id, first.id]);
+        assert_eq!(store.list(Some("game")).unwrap().len(), 1);
+
+        fake.listings.lock().unwrap().clear();
+        assert_eq!(store.load(&first.summary()).unwrap().unwrap().id, first.id);
+        assert_eq!(store.load(&second.summary()).unwrap().unwrap().id, second.id);
+        assert_eq!(store.load_attachment(&first.summary(), "app.log").unwrap().unwrap(), b"log lines");
+        assert!(store.load_attachment(&first.summary(), "other.log").unwrap().is_none());
+        assert!(store.load(&report("game", 1000).summary()).unwrap().is_none());
+
+        assert!(store.delete(&first.summary()).unwrap());
+        assert!(!store.delete(&first.summary()).unwrap());
+        assert!(store.load_attachment(&first.summary(), "app.log").unwrap().is_none());
+        assert_eq!(fake.objects.lock().unwrap().len(), 1);
+
+        // every lookup only listed the report it was after, never the whole bucket
+        let listings = fake.listings.lock().unwrap();
+        assert!(listings.iter().all(|v| v.contains(".json") || v.contains(".attachments/")), "{:?}", listings);
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+use std::sync::Mutex;
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+use uuid::Uuid;
+use rpr_proto::Codec;
+use super::{Attachment, ReportStore, ReportSummary, StoredReport};
+
+// Stores reports as JSON documents in a single SQLite database, compressed or not, and their attachments in a second table
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS reports (
+                id TEXT PRIMARY KEY,
+                app TEXT NOT NULL,
+                received_at INTEGER NOT NULL,
+                data BLOB NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS reports_app_received_at ON reports (app, received_at);
+            CREATE TABLE IF NOT EXISTS attachments (
+                report TEXT NOT NULL,
+                name TEXT NOT NULL,
+                data BLOB NOT NULL,
+                PRIMARY KEY (report, name)
+            );"
+        )?;
+
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+}
+
+impl ReportStore for SqliteStore {
+    fn store(&self, report: &StoredReport, attachments: &[Attachment], codec: Codec) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let transaction = conn.transaction()?;
+        transaction.execute(
+            "INSERT INTO reports (id, app, received_at, data) VALUES (?1, ?2, ?3, ?4)",
+            params![report.id.to_string(), report.app, report.received_at as i64, super::encode(report, codec)?],
+        )?;
+        for i in attachments {
+            transaction.execute(
+                "INSERT INTO attachments (report, name, data) VALUES (?1, ?2, ?3)",
+                params![report.id.to_string(), i.name, i.data],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn load(&self, report: &ReportSummary) -> Result<Option<StoredReport>> {
+        let data: Option<Vec<u8>> = self.conn.lock().unwrap().query_row(
+            "SELECT data FROM reports WHERE id = ?1",
+            params![report.id.to_string()],
+            |row| row.get(0),
+        ).optional()?;
+
+        match data {
+            Some(v) => Ok(Some(super::decode(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn load_attachment(&self, report: &ReportSummary, name: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.conn.lock().unwrap().query_row(
+            "SELECT data FROM attachments WHERE report = ?1 AND name = ?2",
+            params![report.id.to_string(), name],
+            |row| row.get(0),
+        ).optional()?)
+    }
+
+    fn list(&self, app: Option<&str>) -> Result<Vec<ReportSummary>> {
+        let conn = self.conn.lock().unwrap();
+        let mut statement = conn.prepare(
+            "SELECT id, app, received_at FROM reports WHERE ?1 IS NULL OR app = ?1 ORDER BY received_at DESC"
+        )?;
+        let rows = statement.query_map(params![app], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)?))
+        })?;
+
+        let mut reports = vec![];
+        for i in rows {
+            let (id, app, received_at) = i?;
+            reports.push(ReportSummary {
+                id: Uuid::parse_str(&id)?,
+                app,
+                received_at: received_at as u64,
+            });
+        }
+        Ok(reports)
+    }
+
+    fn delete(&self, report: &ReportSummary) -> Result<bool> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM attachments WHERE report = ?1", params![report.id.to_string()])?;
+        let deleted = conn.execute("DELETE FROM reports WHERE id = ?1", params![report.id.to_string()])?;
+        Ok(deleted > 0)
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use anyhow::Result;
+use rpr_proto::{AttachmentHeader, ClientMessage, Codec, ServerMessage, Stream};
+use uuid::Uuid;
+use wherr::wherr;
+
+// A report and its attachments that are being received, in ReportChunks
+pub struct Upload {
+    pub app: String,
+    pub report_size: u32,
+    pub report_hash: u32,
+    pub codec: Codec,
+    pub attachments: Vec<AttachmentHeader>,
+    // The report followed by the attachments
+    pub data: Vec<u8>,
+    updated: Instant,
+}
+
+impl Upload {
+    pub fn new(app: &str, report_size: u32, report_hash: u32, codec: Codec, attachments: Vec<AttachmentHeader>) -> Self {
+        Self {
+            app: app.to_string(),
+            report_size,
+            report_hash,
+            codec,
+            attachments,
+            data: vec![],
+            updated: Instant::now(),
+        }
+    }
+
+    // Of the report and all attachments together
+    pub fn size(&self) -> usize {
+        self.report_size as usize + self.attachments.iter().map(|v| v.size as usize).sum::<usize>()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.data.len() == self.size()
+    }
+
+    // The report's data and every attachment's, once the upload is complete
+    pub fn parts(&self) -> (&[u8], Vec<(&AttachmentHeader, &[u8])>) {
+        let (report, mut rest) = self.data.split_at(self.report_size as usize);
+        let mut attachments = vec![];
+        for i in &self.attachments {
+            let (data, remaining) = rest.split_at(i.size as usize);
+            attachments.push((i, data));
+            rest = remaining;
+        }
+        (report, attachments)
+    }
+
+    // Receives chunks until the upload is complete, on an error `data` holds everything received up to it
+    #[wherr]
+    pub fn receive_chunks(&mut self, stream: &mut Stream, max_frame_size: u32) -> Result<()> {
+        while !self.is_complete() {
+            match rpr_proto::receive_message_limited(stream, max_frame_size)? {
+                ClientMessage::ReportChunk { offset, data, checksum } => {
+                    if offset as usize != self.data.len() {
+                        anyhow::bail!("Received chunk at offset {}, expected {}", offset, self.data.len());
+                    }
+                    if rpr_proto::compute_hash(&data) != checksum {
+                        anyhow::bail!("CRC32 does not match for chunk at offset {}", offset);
+                    }
+                    if data.is_empty() || self.data.len() + data.len() > self.size() {
+                        anyhow::bail!("Chunk at offset {} does not fit the announced report size", offset);
+                    }
+                    self.data.extend_from_slice(&data);
+                },
+                _ => anyhow::bail!("Unexpected message during upload"),
+            }
+
+            // the last chunk is answered with ReportReceived once the report is stored
+            if !self.is_complete() {
+                rpr_proto::send_message(stream, ServerMessage::ChunkAck { received: self.data.len() as u32 })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn is_same(&self, other: &Upload) -> bool {
+        self.app == other.app && self.report_size == other.report_size && self.report_hash == other.report_hash
+            && self.codec == other.codec && self.attachments == other.attachments
+    }
+}
+
+// Interrupted uploads by report ID, so a client that lost the connection can continue where it stopped.
+// They are only kept in memory, a restarted server has the client start over.
+pub struct Uploads {
+    expiry: Duration,
+    limit: usize,
+    // Of the received data of all uploads together
+    byte_limit: usize,
+    uploads: Mutex<HashMap<Uuid, Upload>>,
+}
+
+impl Uploads {
+    pub fn new(expiry: Duration, limit: usize, byte_limit: usize) -> Self {
+        Self {
+            expiry,
+            limit,
+            byte_limit,
+            uploads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Takes the interrupted upload out, so only one connection at a time can continue it.
+    // The client has to announce the same report as `announced`, anything else starts over.
+    pub fn resume(&self, id: Uuid, announced: &Upload) -> Option<Upload> {
+        let upload = self.uploads.lock().unwrap().remove(&id)?;
+        if upload.updated.elapsed() > self.expiry || !upload.is_same(announced) {
+            return None;
+        }
+        Some(upload)
+    }
+
+    // Keeps an interrupted upload, dropping the oldest ones when there are too many or they take up too much memory.
+    // An upload that doesn't fit the byte limit by itself isn't kept.
+    pub fn keep(&self, id: Uuid, mut upload: Upload) {
+        if self.limit == 0 || self.expiry.is_zero() || upload.data.is_empty() || upload.data.len() > self.byte_limit {
+            return;
+        }
+        upload.updated = Instant::now();
+        // what's counted is what's held on to
+        upload.data.shrink_to_fit();
+
+        let mut uploads = self.uploads.lock().unwrap();
+        uploads.retain(|_, v| v.updated.elapsed() <= self.expiry);
+        while uploads.len() >= self.limit || uploads.values().map(|v| v.data.len()).sum::<usize>() + upload.data.len() > self.byte_limit {
+            let oldest = match uploads.iter().min_by_key(|(_, v)| v.updated) {
+                Some((k, _)) => *k,
+                None => break,
+            };
+            uploads.remove(&oldest);
+        }
+        uploads.insert(id, upload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const CHUNK_SIZE: usize = 1000;
+
+    fn header(name: &str, data: &[u8]) -> AttachmentHeader {
+        AttachmentHeader { name: name.to_string(), size: data.len() as u32, hash: rpr_proto::compute_hash(data) }
+    }
+
+    // The server side of a connection on which `data[from..to]` is sent in chunks, each one waiting for its ChunkAck
+    fn send_chunks(data: Vec<u8>, from: usize, to: usize) -> Stream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut stream = TcpStream::connect(address).unwrap();
+            let mut offset = from;
+            while offset < to {
+                let chunk = &data[offset..(offset + CHUNK_SIZE).min(to)];
+                rpr_proto::send_message(&mut stream, ClientMessage::ReportChunk {
+                    offset: offset as u32,
+                    data: chunk.to_vec(),
+                    checksum: rpr_proto::compute_hash(chunk),
+                }).unwrap();
+                offset += chunk.len();
+                if offset < data.len() {
+                    // the server hangs up on chunks it refuses
+                    match rpr_proto::receive_message(&mut stream) {
+                        Ok(ServerMessage::ChunkAck { received }) => assert_eq!(received as usize, offset),
+                        _ => return,
+                    }
+                }
+            }
+        });
+        Stream::Plain(listener.accept().unwrap().0)
+    }
+
+    fn upload(size: usize) -> Upload {
+        let mut upload = Upload::new("test", size as u32, 1, Codec::None, vec![]);
+        upload.data = vec![0; size];
+        upload
+    }
+
+    #[test]
+    fn interrupted_upload_resumes() {
+        let report: Vec<u8> = (0..2500).map(|v| v as u8).collect();
+        let log = vec![b'l'; 1200];
+        let state = b"state".to_vec();
+        let data = [report.as_slice(), &log, &state].concat();
+        let announce = || Upload::new("test", report.len() as u32, rpr_proto::compute_hash(&report), Codec::None,
+            vec![header("app.log", &log), header("state.txt", &state)]);
+        let uploads = Uploads::new(Duration::from_secs(600), 8, 1024 * 1024);
+        let id = Uuid::from_u128(1);
+
+        // the connection drops after two chunks
+        let mut first = announce();
+        let mut stream = send_chunks(data.clone(), 0, 2 * CHUNK_SIZE);
+        assert!(first.receive_chunks(&mut stream, 4096).is_err());
+        assert_eq!(first.data.len(), 2 * CHUNK_SIZE);
+        uploads.keep(id, first);
+
+        let mut resumed = uploads.resume(id, &announce()).unwrap();
+        assert_eq!(resumed.data.len(), 2 * CHUNK_SIZE);
+        // taken out, a second connection can't continue it as well
+        assert!(uploads.resume(id, &announce()).is_none());
+
+        let mut stream = send_chunks(data.clone(), resumed.data.len(), data.len());
+        resumed.receive_chunks(&mut stream, 4096).unwrap();
+        assert!(resumed.is_complete());
+
+        let (received, attachments) = resumed.parts();
+        assert_eq!(received, report.as_slice());
+        assert_eq!(attachments.len(), 2);
+        assert_eq!((attachments[0].0.name.as_str(), attachments[0].1), ("app.log", log.as_slice()));
+        assert_eq!((attachments[1].0.name.as_str(), attachments[1].1), ("state.txt", state.as_slice()));
+    }
+
+    #[test]
+    fn chunk_at_wrong_offset() {
+        let data = vec![7; 3000];
+        let mut upload = Upload::new("test", 3000, rpr_proto::compute_hash(&data), Codec::None, vec![]);
+        upload.data = data[..500].to_vec();
+        // the client starts over instead of continuing at 500
+        let mut stream = send_chunks(data, 0, 1000);
+        assert!(upload.receive_chunks(&mut stream, 4096).is_err());
+        assert_eq!(upload.data.len(), 500);
+    }
+
+    #[test]
+    fn different_report_starts_over() {
+        let uploads = Uploads::new(Duration::from_secs(600), 8, 1024 * 1024);
+        let announced = |codec| Upload::new("test", 100, 1, codec, vec![]);
+        let mut upload = announced(Codec::None);
+        upload.data = vec![0; 50];
+
+        uploads.keep(Uuid::from_u128(1), upload);
+        assert!(uploads.resume(Uuid::from_u128(1), &announced(Codec::Zstd)).is_none());
+        assert!(uploads.resume(Uuid::from_u128(1), &announced(Codec::None)).is_none());
+        assert!(uploads.resume(Uuid::from_u128(2), &announced(Codec::None)).is_none());
+    }
+
+    #[test]
+    fn byte_limit_drops_oldest() {
+        let uploads = Uploads::new(Duration::from_secs(600), 8, 100);
+        for (id, size) in [(1, 60), (2, 30), (3, 50), (4, 101)] {
+            uploads.keep(Uuid::from_u128(id), upload(size));
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        assert!(uploads.resume(Uuid::from_u128(1), &upload(60)).is_none());
+        assert!(uploads.resume(Uuid::from_u128(2), &upload(30)).is_some());
+        assert!(uploads.resume(Uuid::from_u128(3), &upload(50)).is_some());
+        // bigger than the limit by itself
+        assert!(uploads.resume(Uuid::from_u128(4), &upload(101)).is_none());
+    }
+
+    #[test]
+    fn count_limit_drops_oldest() {
+        let uploads = Uploads::new(Duration::from_secs(600), 2, 1024);
+        for id in 1..=3 {
+            uploads.keep(Uuid::from_u128(id), upload(10));
+            thread::sleep(Duration::from_millis(2));
+        }
+
+        assert!(uploads.resume(Uuid::from_u128(1), &upload(10)).is_none());
+        assert!(uploads.resume(Uuid::from_u128(2), &upload(10)).is_some());
+        assert!(uploads.resume(Uuid::from_u128(3), &upload(10)).is_some());
+    }
+}
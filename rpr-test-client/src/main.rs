@@ -1,12 +1,10 @@
-use std::io::Write;
 use std::net::{Shutdown, TcpStream};
-use std::panic::PanicInfo;
+use std::panic::PanicHookInfo;
 use anyhow::Result;
 use uuid::Uuid;
-use rpr_proto;
-use rpr_proto::{ClientMessage, ServerMessage};
+use rpr_proto::{ClientMessage, Codec, ServerMessage};
 
-const KEY: &'static str = "ZfAr2p3QdzAasrBNkNH540kGbxu62KTF5uSerJGfx/tZ2P6vqK6HJFYkMxL77lkeFfPfY7Fk+sNgtoCSNtFUwQ==";
+const KEY: &str = "ZfAr2p3QdzAasrBNkNH540kGbxu62KTF5uSerJGfx/tZ2P6vqK6HJFYkMxL77lkeFfPfY7Fk+sNgtoCSNtFUwQ==";
 
 fn main() -> Result<()> {
     std::panic::set_hook(Box::new(move |info| {
@@ -21,12 +19,13 @@ fn main() -> Result<()> {
     panic!("uh oh");
 }
 
-fn submit_backtrace(info: &PanicInfo) -> Result<()> {
+fn submit_backtrace(info: &PanicHookInfo) -> Result<()> {
     println!("Connecting to server");
     let mut stream = TcpStream::connect("fortunecookie.duckdns.org:9001")?;
 
     rpr_proto::send_message(&mut stream, ClientMessage::RequestConnection {
-        application_id: [41, 54, 52, 41, 50, 49]
+        application_id: [41, 54, 52, 41, 50, 49],
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
     })?;
     println!("Sent connection request");
 
@@ -46,10 +45,15 @@ fn submit_backtrace(info: &PanicInfo) -> Result<()> {
     })?;
     println!("Submitted challenge solution");
 
-    let limit = match rpr_proto::receive_message(&mut stream)? {
-        ServerMessage::ConnectionInitialized { size_limit, version } => {
-            println!("Server accepted connection, server version {}, size limit {}KiB", version, size_limit / 1024);
-            size_limit
+    let (limit, codec) = match rpr_proto::receive_message(&mut stream)? {
+        ServerMessage::ConnectionInitialized { size_limit, version, retention_days, codecs, max_attachments, attachment_size_limit } => {
+            println!("Server accepted connection, server version {}, size limit {}KiB, retention {:?} days, codecs {:?}", version, size_limit / 1024, retention_days, codecs);
+            println!("Server accepts {} attachments of up to {}KiB", max_attachments, attachment_size_limit / 1024);
+            (size_limit, Codec::negotiate(&codecs))
+        },
+        ServerMessage::ConnectionRejected { reason } => {
+            println!("Server rejected connection: {}", reason);
+            return Ok(());
         },
         _ => {
             print!("Unexpected message!");
@@ -58,17 +62,50 @@ fn submit_backtrace(info: &PanicInfo) -> Result<()> {
     };
 
     let report = rpr_proto::generate_report(info);
-    let report_bin = report.as_bytes().to_vec();
+    let report_bin = codec.compress(&report.to_bytes()?)?;
+    if report_bin.len() as u32 > limit {
+        println!("Report is bigger than the server's size limit!");
+        return Ok(());
+    }
     rpr_proto::send_message(&mut stream, ClientMessage::SubmitReport {
         report_hash: rpr_proto::compute_hash(&report_bin),
         report_size: report_bin.len() as u32,
+        codec,
+        resume_id: None,
+        attachments: vec![],
     })?;
-    println!("Starting data stream, size {}KiB, CRC32 {}", report_bin.len() / 1024, rpr_proto::compute_hash(&report_bin));
-    stream.write_all(&report_bin)?;
+    let chunk_size = match rpr_proto::receive_message(&mut stream)? {
+        ServerMessage::UploadAccepted { report_id, chunk_size, .. } => {
+            println!("Server accepted upload {}, chunk size {}KiB", Uuid::from_u128(report_id), chunk_size / 1024);
+            chunk_size as usize
+        },
+        _ => {
+            print!("Unexpected message!");
+            return Ok(());
+        }
+    };
+    println!("Starting data stream, size {}KiB, codec {:?}, CRC32 {}", report_bin.len() / 1024, codec, rpr_proto::compute_hash(&report_bin));
+    for (idx, data) in report_bin.chunks(chunk_size).enumerate() {
+        let offset = idx * chunk_size;
+        rpr_proto::send_message(&mut stream, ClientMessage::ReportChunk {
+            offset: offset as u32,
+            data: data.to_vec(),
+            checksum: rpr_proto::compute_hash(data),
+        })?;
+        if offset + data.len() < report_bin.len() {
+            match rpr_proto::receive_message(&mut stream)? {
+                ServerMessage::ChunkAck { received } => println!("Server received {} bytes", received),
+                _ => {
+                    print!("Unexpected message!");
+                    return Ok(());
+                }
+            }
+        }
+    }
 
     match rpr_proto::receive_message(&mut stream)? {
-        ServerMessage::ReportReceived { report_id } => {
-            println!("Server received report, ID {}", Uuid::from_u128(report_id));
+        ServerMessage::ReportReceived { report_id, occurrences } => {
+            println!("Server received report, ID {}, seen {} times", Uuid::from_u128(report_id), occurrences);
         },
         _ => {
             print!("Unexpected message!");
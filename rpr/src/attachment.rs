@@ -0,0 +1,104 @@
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::Arc;
+use base64::{Engine, engine::general_purpose};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_ATTACHMENT_MAX_SIZE: usize = 256 * 1024;
+
+pub type AttachmentProvider = Arc<dyn Fn() -> Vec<u8> + Send + Sync>;
+
+#[derive(Clone)]
+pub enum AttachmentSource {
+    // Read when the panic happens, left out if it doesn't exist
+    File(PathBuf),
+    // Called when the panic happens, inside the panic hook, so a panic in it aborts the process
+    Provider(AttachmentProvider),
+}
+
+// Sent along with the report, e.g. the application's log file or a snapshot of its configuration
+#[derive(Clone)]
+pub struct Attachment {
+    // Letters, digits, '.', '_' and '-', see rpr_proto::check_attachment_name
+    pub name: String,
+    pub source: AttachmentSource,
+    // Bigger attachments are cut down to their last max_size bytes, that's where the latest lines of a log are.
+    // The server has a limit of its own, which is applied the same way.
+    pub max_size: usize,
+}
+
+impl Attachment {
+    pub fn file<S: Into<String>, P: Into<PathBuf>>(name: S, path: P) -> Self {
+        Self {
+            name: name.into(),
+            source: AttachmentSource::File(path.into()),
+            max_size: DEFAULT_ATTACHMENT_MAX_SIZE,
+        }
+    }
+
+    pub fn provider<S: Into<String>, F: Fn() -> Vec<u8> + Send + Sync + 'static>(name: S, provider: F) -> Self {
+        Self {
+            name: name.into(),
+            source: AttachmentSource::Provider(Arc::new(provider)),
+            max_size: DEFAULT_ATTACHMENT_MAX_SIZE,
+        }
+    }
+
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    // None if the file doesn't exist or can't be read, a broken attachment shouldn't cost the report
+    fn read(&self) -> Option<Vec<u8>> {
+        match &self.source {
+            AttachmentSource::File(path) => {
+                let mut file = File::open(path).ok()?;
+                // only the end of a big file is read, logs can get huge
+                let size = file.metadata().ok()?.len();
+                file.seek(SeekFrom::Start(size.saturating_sub(self.max_size as u64))).ok()?;
+                let mut data = vec![];
+                file.take(self.max_size as u64).read_to_end(&mut data).ok()?;
+                Some(data)
+            },
+            AttachmentSource::Provider(provider) => Some(keep_end(provider(), self.max_size)),
+        }
+    }
+}
+
+// The contents of an attachment as they were when the panic happened
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct AttachmentData {
+    pub name: String,
+    #[serde(with = "base64_data")]
+    pub data: Vec<u8>,
+}
+
+pub(crate) fn collect(attachments: &[Attachment]) -> Vec<AttachmentData> {
+    attachments.iter()
+        .filter_map(|v| Some(AttachmentData { name: v.name.clone(), data: v.read()? }))
+        .collect()
+}
+
+pub(crate) fn keep_end(mut data: Vec<u8>, max_size: usize) -> Vec<u8> {
+    if data.len() > max_size {
+        data.drain(..data.len() - max_size);
+    }
+    data
+}
+
+// Spooled reports are JSON, where a byte array would take four times the space
+mod base64_data {
+    use super::*;
+    use serde::{Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&general_purpose::STANDARD.encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        general_purpose::STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
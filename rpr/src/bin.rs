@@ -1,14 +1,15 @@
 use rpr::initialize;
 
 fn main() {
-    let config = rpr::Configuration {
-        interactive: true,
-        use_fallback: true,
-        fallback_address: "[YOUR IP/URL]".to_string(),
-        address: "[YOUR IP/URL]".to_string(),
-        shared_key: "[YOUR KEY]".to_string(), // generate with `openssl rand -base64 64`
-        app_id: [84, 69, 83, 84, 0, 0],
-    };
-    initialize(config);
+    let config = rpr::Configuration::builder()
+        .address("[YOUR IP/URL]")
+        .address("[YOUR FALLBACK IP/URL]")
+        .shared_key("[YOUR KEY]") // generate with `openssl rand -base64 64`
+        .app_id([84, 69, 83, 84, 0, 0])
+        .interactive(true)
+        .build()
+        .expect("Invalid crash reporter configuration");
+    initialize(config).expect("Failed to initialize the crash reporter");
+    rpr::breadcrumb("example", "about to panic");
     panic!("test panic");
-}
\ No newline at end of file
+}
@@ -0,0 +1,233 @@
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use base64::{Engine, engine::general_purpose};
+use crate::{Attachment, Configuration, Endpoint, Outcome, SpoolConfig, TlsConfig, DEFAULT_CONNECT_TIMEOUT, DEFAULT_EXIT_CODE, DEFAULT_IO_TIMEOUT, DEFAULT_MAX_BREADCRUMBS};
+
+// What validate() found wrong with a Configuration, one message per problem
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid crash reporter configuration:")?;
+        for i in &self.0 {
+            write!(f, "\n - {}", i)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Builds a Configuration with the defaults for everything that isn't set, see Configuration::builder()
+pub struct ConfigurationBuilder {
+    config: Configuration,
+}
+
+impl Configuration {
+    pub fn builder() -> ConfigurationBuilder {
+        ConfigurationBuilder {
+            config: Configuration {
+                endpoints: vec![],
+                app_id: [0; 6],
+                shared_key: String::new(),
+                interactive: true,
+                exit_on_panic: true,
+                exit_code: DEFAULT_EXIT_CODE,
+                on_complete: None,
+                connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+                read_timeout: DEFAULT_IO_TIMEOUT,
+                write_timeout: DEFAULT_IO_TIMEOUT,
+                tls: None,
+                spool: None,
+                attachments: vec![],
+                max_breadcrumbs: DEFAULT_MAX_BREADCRUMBS,
+            },
+        }
+    }
+
+    // Checks everything that can be checked without connecting to the server, initialize() does this as well
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = vec![];
+
+        if self.endpoints.is_empty() {
+            problems.push("at least one server address is required".to_string());
+        }
+        for i in &self.endpoints {
+            if let Err(e) = check_address(&i.address) {
+                problems.push(format!("invalid server address '{}': {}", i.address, e));
+            }
+        }
+
+        if self.app_id == [0; 6] {
+            problems.push("the application ID is not set".to_string());
+        }
+        if self.shared_key.is_empty() {
+            problems.push("the shared key is not set".to_string());
+        } else if !general_purpose::STANDARD.decode(&self.shared_key).is_ok_and(|v| !v.is_empty()) {
+            problems.push("the shared key is not valid base64".to_string());
+        }
+
+        if self.connect_timeout.is_zero() || self.read_timeout.is_zero() || self.write_timeout.is_zero() {
+            problems.push("timeouts must not be zero".to_string());
+        }
+
+        if let Some(tls) = &self.tls {
+            if let Err(e) = rpr_proto::tls::check_server_name(&tls.server_name) {
+                problems.push(format!("invalid TLS server name '{}': {}", tls.server_name, e));
+            }
+            if let Err(e) = rpr_proto::tls::client_config(tls.ca_certificate.as_deref(), tls.pinned_certificate) {
+                problems.push(format!("invalid TLS configuration: {}", e));
+            }
+        }
+
+        if let Some(spool) = &self.spool {
+            if spool.max_size == 0 || spool.max_age.is_zero() {
+                problems.push("the spool's size and age limits must not be zero".to_string());
+            }
+        }
+
+        for (idx, i) in self.attachments.iter().enumerate() {
+            if let Err(e) = rpr_proto::check_attachment_name(&i.name) {
+                problems.push(format!("invalid attachment name '{}': {}", i.name, e));
+            } else if self.attachments[..idx].iter().any(|v| v.name == i.name) {
+                problems.push(format!("attachment name '{}' is used twice", i.name));
+            }
+            if i.max_size == 0 {
+                problems.push(format!("the size limit of attachment '{}' must not be zero", i.name));
+            }
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(ConfigError(problems)),
+        }
+    }
+}
+
+impl ConfigurationBuilder {
+    // Adds a server address (host:port) that is tried once, endpoints are tried in the order they are added
+    pub fn address<S: Into<String>>(self, address: S) -> Self {
+        self.endpoint(Endpoint::new(address))
+    }
+
+    pub fn endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.config.endpoints.push(endpoint);
+        self
+    }
+
+    pub fn app_id(mut self, app_id: [u8; 6]) -> Self {
+        self.config.app_id = app_id;
+        self
+    }
+
+    // The base64 encoded key of the application on the server
+    pub fn shared_key<S: Into<String>>(mut self, shared_key: S) -> Self {
+        self.config.shared_key = shared_key.into();
+        self
+    }
+
+    pub fn interactive(mut self, interactive: bool) -> Self {
+        self.config.interactive = interactive;
+        self
+    }
+
+    pub fn exit_on_panic(mut self, exit_on_panic: bool) -> Self {
+        self.config.exit_on_panic = exit_on_panic;
+        self
+    }
+
+    pub fn exit_code(mut self, exit_code: i32) -> Self {
+        self.config.exit_code = exit_code;
+        self
+    }
+
+    pub fn on_complete<F: Fn(&Outcome) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.config.on_complete = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    // Sets both the read and the write timeout
+    pub fn io_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = timeout;
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.config.tls = Some(tls);
+        self
+    }
+
+    pub fn spool(mut self, spool: SpoolConfig) -> Self {
+        self.config.spool = Some(spool);
+        self
+    }
+
+    pub fn attach(mut self, attachment: Attachment) -> Self {
+        self.config.attachments.push(attachment);
+        self
+    }
+
+    pub fn max_breadcrumbs(mut self, max_breadcrumbs: usize) -> Self {
+        self.config.max_breadcrumbs = max_breadcrumbs;
+        self
+    }
+
+    pub fn build(self) -> Result<Configuration, ConfigError> {
+        self.config.validate()?;
+        Ok(self.config)
+    }
+}
+
+// Only the syntax is checked, resolving the name is left to the connection attempt
+fn check_address(address: &str) -> Result<(), &'static str> {
+    if address.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+
+    let (host, port) = address.rsplit_once(':').ok_or("expected host:port")?;
+    if port.parse::<u16>().map_err(|_| "invalid port")? == 0 {
+        return Err("invalid port");
+    }
+    let valid_label = |v: &str| !v.is_empty() && v.len() <= 63 && !v.starts_with('-') && !v.ends_with('-')
+        && v.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if host.len() > 253 || !host.trim_end_matches('.').split('.').all(valid_label) {
+        return Err("invalid host name");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn problems(shared_key: &str) -> Vec<String> {
+        let result = Configuration::builder().address("localhost:9001").app_id([1, 2, 3, 4, 5, 6]).shared_key(shared_key).build();
+        match result {
+            Ok(_) => vec![],
+            Err(e) => e.0,
+        }
+    }
+
+    #[test]
+    fn shared_key() {
+        assert!(problems("aGVsbG8=").is_empty());
+        assert_eq!(problems(""), ["the shared key is not set"]);
+        assert_eq!(problems("not base64!"), ["the shared key is not valid base64"]);
+        assert_eq!(problems("aGVsbG8"), ["the shared key is not valid base64"]);
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let result = Configuration::builder().io_timeout(Duration::ZERO).build();
+        assert_eq!(result.err().unwrap().0.len(), 4);
+    }
+}
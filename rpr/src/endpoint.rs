@@ -0,0 +1,128 @@
+use std::fmt::{self, Display, Formatter};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+use rpr_proto::Stream;
+use rpr_proto::tls::ClientConfig;
+use crate::Configuration;
+
+pub const DEFAULT_BACKOFF: Duration = Duration::from_secs(1);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+// A server to submit reports to, the endpoints are tried in the order they are configured
+#[derive(Clone)]
+pub struct Endpoint {
+    // host:port, every address a host name resolves to is tried
+    pub address: String,
+    // Attempts after the first one failed, before moving on to the next endpoint
+    pub retries: u32,
+    // Wait before the first retry, doubled for every following one up to max_backoff
+    pub backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Endpoint {
+    pub fn new<S: Into<String>>(address: S) -> Self {
+        Self {
+            address: address.into(),
+            retries: 0,
+            backoff: DEFAULT_BACKOFF,
+            max_backoff: DEFAULT_MAX_BACKOFF,
+        }
+    }
+
+    pub fn with_retries(mut self, retries: u32, backoff: Duration) -> Self {
+        self.retries = retries;
+        self.backoff = backoff;
+        self
+    }
+
+    fn delay(&self, retry: u32) -> Duration {
+        self.backoff.saturating_mul(2u32.saturating_pow(retry.saturating_sub(1))).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug)]
+pub struct Attempt {
+    pub endpoint: String,
+    // None if the endpoint's address could not be resolved
+    pub address: Option<SocketAddr>,
+    // 0 for the first attempt, counting up with every retry
+    pub retry: u32,
+    pub error: anyhow::Error,
+}
+
+// None of the endpoints could be reached, lists every attempt in the order they were made
+#[derive(Debug)]
+pub struct ConnectError {
+    pub attempts: Vec<Attempt>,
+}
+
+impl Display for ConnectError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.attempts.is_empty() {
+            return write!(f, "No crash report server endpoints are configured");
+        }
+
+        write!(f, "Unable to connect to any crash report server:")?;
+        for i in &self.attempts {
+            write!(f, "\n - {}", i.endpoint)?;
+            if let Some(address) = i.address {
+                write!(f, " ({})", address)?;
+            }
+            if i.retry > 0 {
+                write!(f, ", retry {}", i.retry)?;
+            }
+            write!(f, ": {}", i.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConnectError {}
+
+// Tries every endpoint until one accepts the connection, returns the stream and the endpoint it's connected to
+pub(crate) fn connect(cfg: &Configuration, tls: Option<&Arc<ClientConfig>>, verbose: bool) -> Result<(Stream, String), ConnectError> {
+    let mut attempts = vec![];
+    for endpoint in &cfg.endpoints {
+        for retry in 0..=endpoint.retries {
+            if retry > 0 {
+                let delay = endpoint.delay(retry);
+                if verbose {
+                    println!("Unable to connect to {}, retrying in {:.1}s", endpoint.address, delay.as_secs_f32());
+                }
+                std::thread::sleep(delay);
+            }
+
+            let addresses = match endpoint.address.to_socket_addrs() {
+                Ok(v) => v,
+                Err(e) => {
+                    attempts.push(Attempt { endpoint: endpoint.address.clone(), address: None, retry, error: e.into() });
+                    continue;
+                }
+            };
+
+            for address in addresses {
+                match open(address, cfg, tls) {
+                    Ok(stream) => return Ok((stream, endpoint.address.clone())),
+                    Err(error) => attempts.push(Attempt { endpoint: endpoint.address.clone(), address: Some(address), retry, error }),
+                }
+            }
+        }
+    }
+
+    Err(ConnectError { attempts })
+}
+
+fn open(address: SocketAddr, cfg: &Configuration, tls: Option<&Arc<ClientConfig>>) -> anyhow::Result<Stream> {
+    let tcp = TcpStream::connect_timeout(&address, cfg.connect_timeout).map_err(rpr_proto::io_error)?;
+    tcp.set_read_timeout(Some(cfg.read_timeout))?;
+    tcp.set_write_timeout(Some(cfg.write_timeout))?;
+
+    let mut stream = match (tls, &cfg.tls) {
+        (Some(config), Some(tls)) => rpr_proto::tls::client_stream(tcp, config.clone(), &tls.server_name)?,
+        _ => Stream::Plain(tcp),
+    };
+    stream.handshake().map_err(rpr_proto::io_error)?;
+    Ok(stream)
+}
@@ -1,14 +1,34 @@
+use std::fmt::{self, Display, Formatter};
 use std::io::Write;
-use std::net::{Shutdown, TcpStream};
+use std::net::Shutdown;
 use std::panic::PanicHookInfo;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 use text_io::read;
 use uuid::Uuid;
-use rpr_proto::{ClientMessage, ServerMessage};
+use rpr_proto::{AttachmentHeader, ClientMessage, Codec, CrashReport, ServerMessage};
+use rpr_proto::tls::ClientConfig;
+use crate::attachment::AttachmentData;
+use crate::spool::Spool;
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-const SERVER_VERSION: u8 = 1;
-const HELP: &'static str = r#"Commands:
+mod attachment;
+mod builder;
+mod endpoint;
+mod spool;
+
+pub use attachment::{Attachment, AttachmentProvider, AttachmentSource, DEFAULT_ATTACHMENT_MAX_SIZE};
+pub use builder::{ConfigError, ConfigurationBuilder};
+pub use endpoint::{Attempt, ConnectError, Endpoint, DEFAULT_BACKOFF, DEFAULT_MAX_BACKOFF};
+pub use rpr_proto::{Breadcrumb, CaptureLogger, LogRecord, DEFAULT_MAX_BREADCRUMBS, DEFAULT_MAX_LOG_RECORDS};
+pub use spool::{SpoolConfig, DEFAULT_SPOOL_MAX_AGE, DEFAULT_SPOOL_MAX_SIZE};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+pub const DEFAULT_EXIT_CODE: i32 = -1;
+// Times an upload that was interrupted after it started is resumed on a new connection
+const RESUME_ATTEMPTS: u32 = 3;
+const HELP: &str = r#"Commands:
  y  - Submit crash report
  n  - Do not submit crash report
  v  - View crash report
@@ -17,29 +37,174 @@ ver - Version info
 cfg - Configuration info
 "#;
 
+// The configuration passed to initialize(), used by flush_pending()
+static CONFIG: RwLock<Option<Configuration>> = RwLock::new(None);
+// Held while flushing, so an explicit flush_pending() waits for the one started by initialize()
+static FLUSH: Mutex<()> = Mutex::new(());
+
+// Progress output, only shown while handling a panic
+macro_rules! progress {
+    ($verbose:expr, $($arg:tt)*) => {
+        if $verbose {
+            print!($($arg)*);
+            let _ = std::io::stdout().flush();
+        }
+    };
+}
+
 #[derive(Clone)]
 pub struct Configuration {
-    pub address: String,
-    pub use_fallback: bool,
-    // Used if the address failed to connect (i.e. because it's an external IP) and fallback is enabled, fallback can be used for internal IP when testing
-    pub fallback_address: String,
+    // Tried in order until one accepts the connection, e.g. an external address followed by an internal one for testing
+    pub endpoints: Vec<Endpoint>,
     pub app_id: [u8; 6],
     pub shared_key: String,
     // Set to false to automatically submit on panic (i.e. daemons), true to ask the user for permission
-    pub interactive: bool
+    pub interactive: bool,
+    // Exit the process once the panic was handled, whatever the outcome. Set to false to return from the panic hook instead,
+    // so the panic unwinds as usual and can be caught with catch_unwind or JoinHandle::join
+    pub exit_on_panic: bool,
+    // Exit code used with exit_on_panic
+    pub exit_code: i32,
+    // Called once the panic was handled and before exiting, e.g. to flush logs or notify a supervisor.
+    // It runs inside the panic hook, so a panic in the callback aborts the process.
+    pub on_complete: Option<CompletionCallback>,
+    // Timeouts for establishing a connection and for every single read and write on it,
+    // these make sure a stalled server can't keep the crashed process alive forever
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub write_timeout: Duration,
+    // Set to encrypt the connection to the server
+    pub tls: Option<TlsConfig>,
+    // Set to keep reports that couldn't be submitted and retry them later
+    pub spool: Option<SpoolConfig>,
+    // Sent along with the report, read when the panic happens
+    pub attachments: Vec<Attachment>,
+    // Breadcrumbs kept for the report, the oldest ones are dropped first. 0 stops recording them
+    pub max_breadcrumbs: usize,
+}
+
+#[derive(Clone)]
+pub struct TlsConfig {
+    // Name the server's certificate was issued for
+    pub server_name: String,
+    // PEM encoded CA certificate(s) the server's certificate has to be signed by
+    pub ca_certificate: Option<Vec<u8>>,
+    // SHA-256 fingerprint of the server's certificate, see rpr_proto::tls::parse_fingerprint
+    pub pinned_certificate: Option<[u8; 32]>,
 }
 
-pub fn initialize(cfg: Configuration) {
+pub type CompletionCallback = Arc<dyn Fn(&Outcome) + Send + Sync>;
+
+// What happened to the report of a panic
+#[derive(Debug, Clone)]
+pub enum Outcome {
+    Submitted(Uuid),
+    // The user chose not to submit the report
+    Declined,
+    // The server couldn't be reached, the report will be submitted later
+    Spooled,
+    Failed(String),
+}
+
+// The server refused the report, submitting it again won't help
+#[derive(Debug)]
+pub struct Rejected(pub String);
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+// Installs the panic hook, fails if the configuration is invalid
+pub fn initialize(cfg: Configuration) -> Result<(), ConfigError> {
+    cfg.validate()?;
+    rpr_proto::set_max_breadcrumbs(cfg.max_breadcrumbs);
+    let spooling = cfg.spool.is_some();
+    *CONFIG.write().unwrap() = Some(cfg.clone());
+
+    // the hook that was installed before, usually the default one printing the panic message, keeps running first
+    let previous = std::panic::take_hook();
     std::panic::set_hook(Box::new(move |info| {
-        match panic_handler(info, &cfg) {
-            Ok(_) => (),
-            Err(e) => println!("Fatal error occured during crash report submission: {}", e),
+        previous(info);
+        let outcome = match panic_handler(info, &cfg) {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Fatal error occured during crash report submission: {}", e);
+                Outcome::Failed(e.to_string())
+            },
+        };
+
+        if let Some(callback) = &cfg.on_complete {
+            callback(&outcome);
+        }
+        if cfg.exit_on_panic {
+            std::process::exit(cfg.exit_code);
         }
-    }))
+    }));
+
+    // in the background, the application shouldn't wait for a server that may still be unreachable
+    if spooling {
+        std::thread::spawn(|| {
+            let _ = flush_pending();
+        });
+    }
+    Ok(())
+}
+
+// Records something the application is doing, the most recent breadcrumbs are included in the crash report.
+// Can be called before initialize(), e.g. breadcrumb("network", "connected to the game server")
+pub fn breadcrumb(category: &str, message: &str) {
+    rpr_proto::record_breadcrumb(category, message);
 }
 
-fn panic_handler(info: &PanicHookInfo, cfg: &Configuration) -> anyhow::Result<()> {
+// Submits the reports that were spooled because the server couldn't be reached, returns how many were submitted.
+// Stops at the first report that can't be submitted, the rest is tried again next time.
+pub fn flush_pending() -> anyhow::Result<usize> {
+    let cfg = match CONFIG.read().unwrap().clone() {
+        Some(v) => v,
+        None => anyhow::bail!("rpr::initialize has not been called"),
+    };
+    let spool = match &cfg.spool {
+        Some(v) => Spool::new(v),
+        None => return Ok(0),
+    };
+
+    let _guard = FLUSH.lock().unwrap_or_else(|e| e.into_inner());
+    let mut submitted = 0;
+    for path in spool.pending()? {
+        let claimed = match spool.claim(&path)? {
+            Some(v) => v,
+            None => continue,
+        };
+        let spooled = match spool.load(&claimed) {
+            Ok(v) => v,
+            Err(_) => {
+                // unreadable, it never will be
+                spool.remove(&claimed)?;
+                continue;
+            }
+        };
+
+        match submit_report(&spooled.report, &spooled.attachments, &cfg, false) {
+            Ok(_) => submitted += 1,
+            Err(e) if e.is::<Rejected>() => (),
+            Err(e) => {
+                spool.release(&claimed)?;
+                return Err(e);
+            },
+        }
+        spool.remove(&claimed)?;
+    }
+
+    Ok(submitted)
+}
+
+fn panic_handler(info: &PanicHookInfo, cfg: &Configuration) -> anyhow::Result<Outcome> {
     let report = rpr_proto::generate_report(info);
+    let attachments = attachment::collect(&cfg.attachments);
 
     if cfg.interactive {
         println!("Oops! It seems the application has crashed!");
@@ -50,65 +215,108 @@ fn panic_handler(info: &PanicHookInfo, cfg: &Configuration) -> anyhow::Result<()
     }
 
     // loop only when interactive
-    while cfg.interactive {
-        print!("crash-reporter > ");
-        let cmd: String = read!("{}\n");
-
-        // trim to fix windows \r stuff
-        match cmd.trim_matches('\r').to_lowercase().as_str() {
-            "n" | "q" | "quit" | "exit" => {
-                println!("Exiting...");
-                std::process::exit(-1);
-            },
-            "h" | "help" => {
-                println!("{}", HELP);
-            }
-            "cfg" => {
-                println!("Configuration:");
-                println!("Server address: {}", cfg.address);
-                println!("Fallback address: {} [In use: {}]", cfg.fallback_address, cfg.use_fallback);
-                println!("Application ID: {}", String::from_utf8_lossy(&cfg.app_id));
-            }
-            "v" => {
-                println!(" --- CRASH REPORT ---");
-                println!("{}", report);
-            }
-            "ver" => {
-                println!("crash-reporter shell v{}", VERSION);
-            }
-            "y" => {
-                break;
-            }
-            other => {
-                println!("'{}' is not a valid command", other);
+    if cfg.interactive {
+        loop {
+            print!("crash-reporter > ");
+            let cmd: String = read!("{}\n");
+
+            // trim to fix windows \r stuff
+            match cmd.trim_matches('\r').to_lowercase().as_str() {
+                "n" | "q" | "quit" | "exit" => {
+                    if cfg.exit_on_panic {
+                        println!("Exiting...");
+                    }
+                    return Ok(Outcome::Declined);
+                },
+                "h" | "help" => {
+                    println!("{}", HELP);
+                }
+                "cfg" => {
+                    println!("Configuration:");
+                    for (idx, endpoint) in cfg.endpoints.iter().enumerate() {
+                        println!("Server address {}: {} [Retries: {}]", idx + 1, endpoint.address, endpoint.retries);
+                    }
+                    println!("Application ID: {}", String::from_utf8_lossy(&cfg.app_id));
+                    println!("TLS: {}", cfg.tls.is_some());
+                    match &cfg.spool {
+                        Some(v) => println!("Spool directory: {}", v.directory.display()),
+                        None => println!("Spool directory: none"),
+                    }
+                    for i in &cfg.attachments {
+                        println!("Attachment: {}", i.name);
+                    }
+                }
+                "v" => {
+                    println!(" --- CRASH REPORT ---");
+                    println!("{}", report);
+                    for i in &attachments {
+                        println!("Attached: {} ({} bytes)", i.name, i.data.len());
+                    }
+                }
+                "ver" => {
+                    println!("crash-reporter shell v{}", VERSION);
+                }
+                "y" => {
+                    break;
+                }
+                other => {
+                    println!("'{}' is not a valid command", other);
+                }
             }
         }
     }
 
-    print!("Connecting to crash report server...  ");
-    std::io::stdout().flush(); // make sure we print the above to the terminal
-    let mut stream = match TcpStream::connect(&cfg.address) {
-        Ok(s) => s,
-        Err(_) => {
-            // fall back to local IP
-            if cfg.use_fallback {
-                println!("Falling back to {}", cfg.fallback_address);
-            } else {
-                anyhow::bail!("Failed to connect to panic-report server!")
-            }
-
-            match TcpStream::connect(&cfg.fallback_address) {
-                Ok(s) => s,
-                Err(_) => anyhow::bail!("Failed to connect to panic-report fallback server!"),
+    match submit_report(&report, &attachments, cfg, true) {
+        Ok(id) => {
+            if cfg.interactive {
+                println!("Thank you for submitting the crash report!");
             }
+            Ok(Outcome::Submitted(id))
+        },
+        Err(e) => match &cfg.spool {
+            Some(spool) if !e.is::<Rejected>() => {
+                println!("Unable to submit the crash report: {}", e);
+                Spool::new(spool).save(&report, &attachments)?;
+                println!("The report was saved and will be submitted the next time the application starts");
+                Ok(Outcome::Spooled)
+            },
+            _ => Err(e),
         },
+    }
+}
+
+// Connects to the server and submits the report, `verbose` prints the progress.
+// If the connection drops during the upload, it is resumed where it stopped on a new connection.
+fn submit_report(report: &CrashReport, attachments: &[AttachmentData], cfg: &Configuration, verbose: bool) -> anyhow::Result<Uuid> {
+    let report_bin = report.to_bytes()?;
+    let tls = match &cfg.tls {
+        Some(tls) => Some(rpr_proto::tls::client_config(tls.ca_certificate.as_deref(), tls.pinned_certificate)?),
+        None => None,
     };
-    println!("Connected!");
-    std::io::stdout().flush();
+
+    let mut resume_id = None;
+    let mut attempt = 0;
+    loop {
+        match upload_report(&report_bin, attachments, cfg, tls.as_ref(), verbose, &mut resume_id) {
+            Err(e) if resume_id.is_some() && attempt < RESUME_ATTEMPTS && !e.is::<Rejected>() => {
+                attempt += 1;
+                progress!(verbose, "\nUpload interrupted: {}\n", e);
+            },
+            result => return result,
+        }
+    }
+}
+
+// One connection's worth of submit_report, `resume_id` is set once the server has assigned the upload an ID
+fn upload_report(report_bin: &[u8], attachments: &[AttachmentData], cfg: &Configuration, tls: Option<&Arc<ClientConfig>>, verbose: bool, resume_id: &mut Option<u128>) -> anyhow::Result<Uuid> {
+    progress!(verbose, "Connecting to crash report server...\n");
+    let (mut stream, endpoint) = endpoint::connect(cfg, tls, verbose)?;
+    progress!(verbose, "Connected to {}\n", endpoint);
     rpr_proto::send_message(&mut stream, ClientMessage::RequestConnection {
-        application_id: cfg.app_id
+        application_id: cfg.app_id,
+        client_version: VERSION.to_string(),
     })?;
-    print!("Authorizing... ");
+    progress!(verbose, "Authorizing... ");
 
     let solution = match rpr_proto::receive_message(&mut stream)? {
         ServerMessage::Challenge { data } => rpr_proto::solve_challenge(data, &cfg.shared_key)?,
@@ -118,48 +326,107 @@ fn panic_handler(info: &PanicHookInfo, cfg: &Configuration) -> anyhow::Result<()
     rpr_proto::send_message(&mut stream, ClientMessage::InitializeConnection {
         challenge_response: solution
     })?;
-    let limit = match rpr_proto::receive_message(&mut stream)? {
-        ServerMessage::ConnectionInitialized { size_limit, version } => {
+    let (limit, retention_days, codecs, max_attachments, attachment_limit) = match rpr_proto::receive_message(&mut stream)? {
+        ServerMessage::ConnectionInitialized { size_limit, version, retention_days, codecs, max_attachments, attachment_size_limit } => {
             //println!("Server accepted connection, server version {}, size limit {}KiB", version, size_limit / 1024);
-            if version != SERVER_VERSION {
-                anyhow::bail!("Server version mismatch!");
+            if version != rpr_proto::PROTOCOL_VERSION {
+                return Err(Rejected("Server version mismatch!".to_string()).into());
             }
-            size_limit
+            (size_limit, retention_days, codecs, max_attachments as usize, attachment_size_limit as usize)
         },
+        ServerMessage::ConnectionRejected { reason } => return Err(Rejected(format!("Server rejected the connection: {}", reason)).into()),
         _ => anyhow::bail!("Unexpected message!")
     };
-    println!("Accepted");
+    progress!(verbose, "Accepted\n");
+    if let (true, Some(days)) = (verbose && cfg.interactive, retention_days) {
+        println!("The server keeps crash reports for {} days", days);
+    }
 
-    let report_bin = report.as_bytes().to_vec();
+    // compression is deterministic, so a resumed upload sends the same data as long as the server offers the same codecs
+    let codec = Codec::negotiate(&codecs);
+    let compressed = codec.compress(report_bin)?;
+    // compressing a tiny report can make it bigger
+    let (codec, report_bin) = match compressed.len() < report_bin.len() {
+        true => (codec, compressed),
+        false => (Codec::None, report_bin.to_vec()),
+    };
     if report_bin.len() as u32 > limit {
-        println!("Report is bigger than server's size limit!");
-        println!("Unable to submit report!");
-        anyhow::bail!("Report too big!");
+        progress!(verbose, "Report is bigger than server's size limit!\n");
+        return Err(Rejected("Report too big!".to_string()).into());
+    }
+
+    // sent in the same upload, right after the report
+    let mut upload = report_bin.clone();
+    let mut headers = vec![];
+    if attachments.len() > max_attachments {
+        progress!(verbose, "The server accepts at most {} attachments, leaving out the rest\n", max_attachments);
+    }
+    for i in attachments.iter().take(max_attachments) {
+        let data = codec.compress(&attachment::keep_end(i.data.clone(), attachment_limit))?;
+        if data.len() > attachment_limit {
+            progress!(verbose, "Attachment '{}' is bigger than the server's size limit, leaving it out\n", i.name);
+            continue;
+        }
+        headers.push(AttachmentHeader { name: i.name.clone(), size: data.len() as u32, hash: rpr_proto::compute_hash(&data) });
+        upload.extend_from_slice(&data);
     }
 
-    print!("Announcing crash report... ");
-    std::io::stdout().flush();
+    progress!(verbose, "Announcing crash report... ");
     rpr_proto::send_message(&mut stream, ClientMessage::SubmitReport {
         report_hash: rpr_proto::compute_hash(&report_bin),
         report_size: report_bin.len() as u32,
+        codec,
+        resume_id: *resume_id,
+        attachments: headers,
     })?;
-    println!("done");
+    let (mut offset, chunk_size) = match rpr_proto::receive_message(&mut stream)? {
+        ServerMessage::UploadAccepted { report_id, offset, chunk_size } => {
+            *resume_id = Some(report_id);
+            (offset as usize, chunk_size as usize)
+        },
+        _ => anyhow::bail!("Unexpected message!")
+    };
+    if offset > upload.len() || chunk_size == 0 {
+        anyhow::bail!("Server sent an invalid upload offset or chunk size");
+    }
+    progress!(verbose, "done\n");
+    if offset > 0 {
+        progress!(verbose, "Resuming upload at {}KiB\n", offset / 1024);
+    }
 
-    print!("Starting crash report data stream... ");
-    std::io::stdout().flush();
-    stream.write_all(&report_bin)?;
-    println!("report sent");
+    while offset < upload.len() {
+        let data = &upload[offset..upload.len().min(offset + chunk_size)];
+        rpr_proto::send_message(&mut stream, ClientMessage::ReportChunk {
+            offset: offset as u32,
+            data: data.to_vec(),
+            checksum: rpr_proto::compute_hash(data),
+        })?;
+        offset += data.len();
 
-    match rpr_proto::receive_message(&mut stream)? {
-        ServerMessage::ReportReceived { report_id } => println!("Crash report received, report ID {}", Uuid::from_u128(report_id)),
+        // the last chunk is answered with ReportReceived
+        if offset < upload.len() {
+            match rpr_proto::receive_message(&mut stream)? {
+                ServerMessage::ChunkAck { received } if received as usize == offset => (),
+                _ => anyhow::bail!("Unexpected message!")
+            }
+        }
+        progress!(verbose, "\rSending crash report... {}%", offset * 100 / upload.len());
+    }
+    progress!(verbose, "\n");
+
+    let report_id = match rpr_proto::receive_message(&mut stream)? {
+        ServerMessage::ReportReceived { report_id, occurrences } => {
+            let report_id = Uuid::from_u128(report_id);
+            progress!(verbose, "Crash report received, report ID {}\n", report_id);
+            if verbose && cfg.interactive && occurrences > 1 {
+                println!("This crash is already known to the developers, it has been reported {} times", occurrences);
+            }
+            report_id
+        },
         _ => anyhow::bail!("Unexpected message!")
     };
 
     stream.shutdown(Shutdown::Both)?;
 
-    if cfg.interactive {
-        println!("Thank you for submitting the crash report!");
-    }
-
-    std::process::exit(-1);
+    Ok(report_id)
 }
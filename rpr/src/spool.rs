@@ -0,0 +1,320 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use rpr_proto::CrashReport;
+use crate::attachment::AttachmentData;
+
+pub const DEFAULT_SPOOL_MAX_SIZE: u64 = 10 * 1024 * 1024;
+pub const DEFAULT_SPOOL_MAX_AGE: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+// A claimed report that hasn't been submitted or released after this long belongs to a process that died
+const STALE_CLAIM: Duration = Duration::from_secs(10 * 60);
+// Writing a report takes moments, a temporary file that is older was left behind by a save that didn't finish
+const STALE_TEMPORARY: Duration = Duration::from_secs(60);
+
+// Where reports are kept when the server can't be reached, they are submitted on the next initialize() or flush_pending()
+#[derive(Clone)]
+pub struct SpoolConfig {
+    pub directory: PathBuf,
+    // Total size of the spooled reports in bytes, the oldest reports are dropped first
+    pub max_size: u64,
+    // Reports older than this are dropped without submitting them
+    pub max_age: Duration,
+}
+
+impl SpoolConfig {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+            max_size: DEFAULT_SPOOL_MAX_SIZE,
+            max_age: DEFAULT_SPOOL_MAX_AGE,
+        }
+    }
+}
+
+// What a spooled report file holds, files spooled before attachments were supported hold a bare CrashReport
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SpooledReport {
+    pub report: CrashReport,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentData>,
+}
+
+pub(crate) struct Spool<'a> {
+    config: &'a SpoolConfig,
+}
+
+impl<'a> Spool<'a> {
+    pub fn new(config: &'a SpoolConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn save(&self, report: &CrashReport, attachments: &[AttachmentData]) -> Result<PathBuf> {
+        let data = serde_json::to_vec(&SpooledReport { report: report.clone(), attachments: attachments.to_vec() })?;
+        if data.len() as u64 > self.config.max_size {
+            anyhow::bail!("Report is bigger than the spool's size limit");
+        }
+
+        fs::create_dir_all(&self.config.directory)?;
+        let path = self.config.directory.join(format!("{}-{}.json", now().as_nanos(), std::process::id()));
+        // written under another name first, so a crash while writing doesn't leave a truncated report behind
+        let temporary = path.with_extension("tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &path)?;
+
+        self.prune()?;
+        Ok(path)
+    }
+
+    // Spooled reports, oldest first, after dropping the ones past the age and size limits
+    pub fn pending(&self) -> Result<Vec<PathBuf>> {
+        Ok(self.prune()?.into_iter().filter(|v| is_report(v)).collect())
+    }
+
+    // Takes the report for submission, returns None if another process or thread was first.
+    // The claim is released with `release` or by removing the report.
+    pub fn claim(&self, path: &Path) -> Result<Option<PathBuf>> {
+        let claimed = path.with_extension("sending");
+        match fs::rename(path, &claimed) {
+            Ok(_) => (),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        // the modification time tells when the claim went stale
+        fs::File::options().write(true).open(&claimed)?.set_modified(SystemTime::now())?;
+        Ok(Some(claimed))
+    }
+
+    pub fn release(&self, claimed: &Path) -> Result<()> {
+        fs::rename(claimed, claimed.with_extension("json"))?;
+        Ok(())
+    }
+
+    pub fn load(&self, path: &Path) -> Result<SpooledReport> {
+        let data = fs::read(path)?;
+        match serde_json::from_slice(&data) {
+            Ok(v) => Ok(v),
+            Err(_) => Ok(SpooledReport { report: CrashReport::from_bytes(&data)?, attachments: vec![] }),
+        }
+    }
+
+    // Also succeeds if the report was dropped by the size limit in the meantime
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    // Returns the remaining reports and claims, oldest first
+    fn prune(&self) -> Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(&self.config.directory) {
+            Ok(v) => v,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut reports = vec![];
+        // size of the reports being saved right now, they count toward the size limit but can't be dropped
+        let mut saving = 0;
+        for i in entries {
+            let mut path = i?.path();
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                match fs::metadata(&path) {
+                    Ok(v) if age(&v).is_some_and(|v| v > STALE_TEMPORARY) => {
+                        let _ = fs::remove_file(&path);
+                    },
+                    Ok(v) => saving += v.len(),
+                    Err(_) => (),
+                }
+                continue;
+            }
+            let written = match written_at(&path) {
+                Some(v) => v,
+                None => continue,
+            };
+            let metadata = match fs::metadata(&path) {
+                Ok(v) => v,
+                // submitted by someone else in the meantime
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+
+            if now().saturating_sub(written) > self.config.max_age {
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            if !is_report(&path) && age(&metadata).is_some_and(|v| v > STALE_CLAIM) {
+                if self.release(&path).is_err() {
+                    continue;
+                }
+                path = path.with_extension("json");
+            }
+            reports.push((written, path, metadata.len()));
+        }
+        reports.sort();
+
+        let mut total: u64 = saving + reports.iter().map(|(_, _, size)| size).sum::<u64>();
+        let mut dropped = 0;
+        for (_, path, size) in &reports {
+            if total <= self.config.max_size {
+                break;
+            }
+            let _ = fs::remove_file(path);
+            total -= size;
+            dropped += 1;
+        }
+
+        Ok(reports.into_iter().skip(dropped).map(|(_, path, _)| path).collect())
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+// Since the file was last modified
+fn age(metadata: &fs::Metadata) -> Option<Duration> {
+    metadata.modified().ok()?.elapsed().ok()
+}
+
+fn is_report(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "json")
+}
+
+// Reports are named {nanoseconds since the UNIX epoch}-{process id}.json, or .sending while claimed
+fn written_at(path: &Path) -> Option<Duration> {
+    if path.extension().is_none_or(|ext| ext != "json" && ext != "sending") {
+        return None;
+    }
+    let (nanos, _) = path.file_stem()?.to_str()?.split_once('-')?;
+    let nanos: u128 = nanos.parse().ok()?;
+    Some(Duration::new((nanos / 1_000_000_000) as u64, (nanos % 1_000_000_000) as u32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn report(message: &str) -> CrashReport {
+        CrashReport { message: Some(message.to_string()), ..Default::default() }
+    }
+
+    fn config(directory: &TempDir) -> SpoolConfig {
+        SpoolConfig::new(directory.path())
+    }
+
+    // Writes a report as if it was spooled `age` ago
+    fn spool_at(config: &SpoolConfig, age: Duration, message: &str) -> PathBuf {
+        let path = config.directory.join(format!("{}-1.json", (now() - age).as_nanos()));
+        fs::write(&path, report(message).to_bytes().unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn save_and_load() {
+        let directory = TempDir::new().unwrap();
+        let config = config(&directory);
+        let spool = Spool::new(&config);
+        let attachments = [AttachmentData { name: "app.log".to_string(), data: b"log".to_vec() }];
+
+        let path = spool.save(&report("first"), &attachments).unwrap();
+        assert_eq!(spool.pending().unwrap(), vec![path.clone()]);
+        let loaded = spool.load(&path).unwrap();
+        assert_eq!(loaded.report.message.as_deref(), Some("first"));
+        assert_eq!(loaded.attachments[0].data, b"log");
+
+        // spooled before attachments existed
+        let bare = spool_at(&config, Duration::from_secs(1), "bare");
+        assert_eq!(spool.load(&bare).unwrap().report.message.as_deref(), Some("bare"));
+        assert!(spool.load(&bare).unwrap().attachments.is_empty());
+    }
+
+    #[test]
+    fn temporary_files() {
+        let directory = TempDir::new().unwrap();
+        let mut config = config(&directory);
+        let report = spool_at(&config, Duration::from_secs(60), "report");
+        let size = fs::metadata(&report).unwrap().len();
+        let saving = config.directory.join(format!("{}-1.tmp", now().as_nanos()));
+        fs::write(&saving, vec![b' '; size as usize]).unwrap();
+        let interrupted = config.directory.join(format!("{}-2.tmp", now().as_nanos()));
+        fs::write(&interrupted, b"{").unwrap();
+        fs::File::options().write(true).open(&interrupted).unwrap().set_modified(SystemTime::now() - STALE_TEMPORARY * 2).unwrap();
+
+        // a save in progress is left alone, one that was interrupted is cleaned up
+        assert_eq!(Spool::new(&config).pending().unwrap(), vec![report.clone()]);
+        assert!(saving.exists());
+        assert!(!interrupted.exists());
+
+        // and the one in progress takes up room in the spool
+        config.max_size = size * 2 - 1;
+        assert!(Spool::new(&config).pending().unwrap().is_empty());
+        assert!(!report.exists());
+        assert!(saving.exists());
+    }
+
+    #[test]
+    fn claims() {
+        let directory = TempDir::new().unwrap();
+        let config = config(&directory);
+        let spool = Spool::new(&config);
+        let path = spool.save(&report("claimed"), &[]).unwrap();
+
+        let claimed = spool.claim(&path).unwrap().unwrap();
+        assert_eq!(claimed.extension().unwrap(), "sending");
+        assert!(!path.exists());
+        // someone else was first
+        assert!(spool.claim(&path).unwrap().is_none());
+        assert!(spool.pending().unwrap().is_empty());
+
+        spool.release(&claimed).unwrap();
+        assert_eq!(spool.pending().unwrap(), vec![path.clone()]);
+        spool.remove(&path).unwrap();
+        spool.remove(&path).unwrap();
+        assert!(spool.pending().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stale_claims_are_released() {
+        let directory = TempDir::new().unwrap();
+        let config = config(&directory);
+        let spool = Spool::new(&config);
+        let stale = spool.claim(&spool_at(&config, Duration::from_secs(3600), "stale")).unwrap().unwrap();
+        let fresh = spool.claim(&spool_at(&config, Duration::from_secs(60), "fresh")).unwrap().unwrap();
+        fs::File::options().write(true).open(&stale).unwrap().set_modified(SystemTime::now() - STALE_CLAIM - Duration::from_secs(60)).unwrap();
+
+        assert_eq!(spool.pending().unwrap(), [stale.with_extension("json")]);
+        assert!(fresh.exists());
+    }
+
+    #[test]
+    fn old_reports_are_dropped() {
+        let directory = TempDir::new().unwrap();
+        let config = config(&directory);
+        let old = spool_at(&config, DEFAULT_SPOOL_MAX_AGE + Duration::from_secs(60), "old");
+        let recent = spool_at(&config, DEFAULT_SPOOL_MAX_AGE - Duration::from_secs(60), "recent");
+
+        assert_eq!(Spool::new(&config).pending().unwrap(), [recent]);
+        assert!(!old.exists());
+    }
+
+    #[test]
+    fn oldest_reports_are_dropped_first() {
+        let directory = TempDir::new().unwrap();
+        let mut config = config(&directory);
+        let paths: Vec<_> = (1..=3).rev().map(|v| spool_at(&config, Duration::from_secs(v * 60), "same size")).collect();
+        let size = fs::metadata(&paths[0]).unwrap().len();
+        config.max_size = size * 2 + size / 2;
+
+        assert_eq!(Spool::new(&config).pending().unwrap(), paths[1..]);
+        assert!(!paths[0].exists());
+
+        // a report that doesn't fit at all isn't spooled
+        config.max_size = size / 2;
+        assert!(Spool::new(&config).save(&report("too big"), &[]).is_err());
+    }
+}